@@ -4,8 +4,12 @@ fn frame(device: &wgpu::Device, framebuffer: &mut wgpu_misc::Framebuffer) -> wgp
     let mut encoder =
         device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-    {
-        let _pass = framebuffer.begin_render_pass(&mut encoder);
+    match framebuffer.try_begin_render_pass(&mut encoder) {
+        Ok(_pass) => {
+            // .. do stuff with pass
+        }
+        Err(err) if err.should_skip_frame() => {}
+        Err(err) => panic!("{err}"),
     }
 
     encoder.finish()
@@ -23,7 +27,7 @@ impl Example {
         let window_attributes = winit::window::WindowAttributes::default().with_visible(false);
         let window = std::sync::Arc::new(event_loop.create_window(window_attributes).unwrap());
         let window2 = window.clone(); // moved to async block
-        let (device, queue, surface) = wgpu_misc::block_on(async move {
+        let (adapter, device, queue, surface) = wgpu_misc::block_on(async move {
             let instance = wgpu::Instance::new(&Default::default());
             let surface = instance.create_surface(window2).expect("surface");
 
@@ -48,11 +52,16 @@ impl Example {
                 .await
                 .expect("Device request");
 
-            (device, queue, surface)
+            (adapter, device, queue, surface)
         });
 
-        let mut framebuffer =
-            wgpu_misc::Framebuffer::new_from_surface(surface, wgpu::TextureFormat::Bgra8UnormSrgb);
+        let mut framebuffer = wgpu_misc::Framebuffer::new_from_surface_negotiated(
+            surface,
+            &adapter,
+            &[wgpu::PresentMode::Mailbox, wgpu::PresentMode::Fifo],
+            &[wgpu::CompositeAlphaMode::Opaque],
+        )
+        .expect("Surface not supported by the adapter");
         let sz = window.inner_size();
         framebuffer.set_resolution(sz.width, sz.height);
         framebuffer.set_depth_stencil_format(Some(wgpu::TextureFormat::Depth24Plus));
@@ -68,7 +77,9 @@ impl Example {
         let cmd_buf = frame(&device, &mut framebuffer);
 
         queue.submit(Some(cmd_buf));
-        framebuffer.present();
+        if framebuffer.needs_present() {
+            framebuffer.present();
+        }
         window.set_visible(true);
 
         Self {
@@ -109,7 +120,9 @@ impl winit::application::ApplicationHandler for ExampleApp {
                 let cmd_buf = frame(&example.device, &mut example.framebuffer);
 
                 example.queue.submit(Some(cmd_buf));
-                example.framebuffer.present();
+                if example.framebuffer.needs_present() {
+                    example.framebuffer.present();
+                }
             }
             WindowEvent::KeyboardInput {
                 event:
@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+
+use crate::fullscreen::{self, FullscreenTarget};
+use crate::{AttachmentKey, Framebuffer, FramebufferError};
+
+const SHADER: &str = r#"
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+
+// The source rectangle in texture coordinates
+struct SourceRect {
+    offset: vec2<f32>,
+    scale: vec2<f32>,
+};
+@group(0) @binding(2) var<uniform> source_rect: SourceRect;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(source, source_sampler, source_rect.offset + in.uv * source_rect.scale);
+}
+"#;
+
+/// Rectangle in texels, with the origin at the top left corner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlitRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl BlitRect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> BlitRect {
+        BlitRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// Options of a blit, by default the whole source covers the whole target
+/// with linear filtering
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlitOptions {
+    /// Part of the source to draw, in texels of its first mip level. None for all of it
+    pub source_rect: Option<BlitRect>,
+    /// Part of the target to draw to, that needs to be inside it. The rest of the
+    /// target keeps its contents. None for all of it, that is cleared first.
+    /// For a multisampled framebuffer attachment the contents are kept in its
+    /// multisample texture, that needs `Framebuffer::set_attachment_store(.., true)`.
+    pub target_rect: Option<BlitRect>,
+    pub filter: wgpu::FilterMode,
+}
+
+impl Default for BlitOptions {
+    fn default() -> Self {
+        BlitOptions {
+            source_rect: None,
+            target_rect: None,
+            filter: wgpu::FilterMode::Linear,
+        }
+    }
+}
+
+impl BlitOptions {
+    pub fn source_rect(mut self, rect: BlitRect) -> Self {
+        self.source_rect = Some(rect);
+        self
+    }
+
+    pub fn target_rect(mut self, rect: BlitRect) -> Self {
+        self.target_rect = Some(rect);
+        self
+    }
+
+    pub fn filter(mut self, filter: wgpu::FilterMode) -> Self {
+        self.filter = filter;
+        self
+    }
+}
+
+/// Slots of `SourceRects` in a buffer
+const SOURCE_RECT_SLOTS: u32 = 64;
+
+/// Size of a source rect, offset and scale
+const SOURCE_RECT_SIZE: u64 = 16;
+
+/// Uniform buffer of the source rects, in slots at the dynamic offset alignment.
+/// The first slot has the whole source, and the rest are written on first use.
+/// A slot is never rewritten, as the passes recorded before the write read the
+/// buffer only after it, so a new buffer is taken when they run out.
+#[derive(Debug)]
+struct SourceRects {
+    buffer: wgpu::Buffer,
+    /// Rects of the slots in use
+    rects: Vec<[f32; 4]>,
+    stride: u32,
+}
+
+impl SourceRects {
+    const WHOLE: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+
+    fn new(device: &wgpu::Device) -> SourceRects {
+        let stride = device.limits().min_uniform_buffer_offset_alignment;
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Blit source rects"),
+            size: stride as u64 * SOURCE_RECT_SLOTS as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: true,
+        });
+        buffer
+            .slice(..SOURCE_RECT_SIZE)
+            .get_mapped_range_mut()
+            .copy_from_slice(&Self::WHOLE.map(f32::to_le_bytes).concat());
+        buffer.unmap();
+        SourceRects {
+            buffer,
+            rects: vec![Self::WHOLE],
+            stride,
+        }
+    }
+
+    /// Returns the dynamic offset of the slot with the rect, writing it to a free slot if needed
+    fn offset(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, rect: [f32; 4]) -> u32 {
+        if let Some(slot) = self.rects.iter().position(|r| *r == rect) {
+            return slot as u32 * self.stride;
+        }
+        if self.rects.len() as u32 == SOURCE_RECT_SLOTS {
+            *self = SourceRects::new(device);
+        }
+        let offset = self.rects.len() as u32 * self.stride;
+        queue.write_buffer(
+            &self.buffer,
+            offset as u64,
+            &rect.map(f32::to_le_bytes).concat(),
+        );
+        self.rects.push(rect);
+        offset
+    }
+}
+
+/// Views a blit renders to
+struct BlitTarget<'a> {
+    view: &'a wgpu::TextureView,
+    resolve_target: Option<&'a wgpu::TextureView>,
+    format: wgpu::TextureFormat,
+    /// Store operation of the view, when it is drawn to as a whole
+    store: wgpu::StoreOp,
+    /// Dynamic offset of the source rect
+    source_rect_offset: u32,
+}
+
+/// Copies textures to render targets with a fullscreen pass, scaling them
+/// to the target size. Unlike `copy_texture_to_texture` the sizes and formats
+/// can differ, such as from an `Rgba16Float` offscreen texture to a
+/// `Bgra8UnormSrgb` surface.
+///
+/// ```rust,no_run
+///    # let (device, queue, mut encoder) : (wgpu::Device, wgpu::Queue, wgpu::CommandEncoder) = unimplemented!();
+///    # let (offscreen, mut window) : (wgpu_misc::Framebuffer, wgpu_misc::Framebuffer) = unimplemented!();
+///    use wgpu_misc::{BlitOptions, BlitRect, Blitter};
+///    let mut blitter = Blitter::new(&device);
+///    // The top left quarter of the offscreen texture, to all of the window
+///    let source_rect = BlitRect::new(0, 0, offscreen.width() / 2, offscreen.height() / 2);
+///    blitter
+///        .blit_to_framebuffer(
+///            &mut encoder,
+///            &queue,
+///            offscreen.attachment_view(0).unwrap(),
+///            &mut window,
+///            0,
+///            &BlitOptions::default().source_rect(source_rect),
+///        )
+///        .expect("Blit");
+///    // .. submit, and window.present()
+/// ```
+#[derive(Debug)]
+pub struct Blitter {
+    device: wgpu::Device,
+    shader: wgpu::ShaderModule,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    nearest_sampler: wgpu::Sampler,
+    linear_sampler: wgpu::Sampler,
+    source_rects: SourceRects,
+    /// Pipelines are created on first use for each target format and sample count
+    pipelines: HashMap<(wgpu::TextureFormat, u32), wgpu::RenderPipeline>,
+}
+
+impl Blitter {
+    pub fn new(device: &wgpu::Device) -> Blitter {
+        let shader = fullscreen::shader_module(device, "Blit shader", SHADER);
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Blit bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(SOURCE_RECT_SIZE),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Blit pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let sampler = |filter| {
+            device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("Blit sampler"),
+                mag_filter: filter,
+                min_filter: filter,
+                ..Default::default()
+            })
+        };
+        Blitter {
+            device: device.clone(),
+            shader,
+            bind_group_layout,
+            pipeline_layout,
+            nearest_sampler: sampler(wgpu::FilterMode::Nearest),
+            linear_sampler: sampler(wgpu::FilterMode::Linear),
+            source_rects: SourceRects::new(device),
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Records a pass that draws the source to a single sampled target.
+    /// The source needs a filterable float format. A `source_rect` is
+    /// written to a uniform buffer with the queue.
+    pub fn blit(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        source: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+        target_format: wgpu::TextureFormat,
+        options: &BlitOptions,
+    ) {
+        let target = BlitTarget {
+            view: target,
+            resolve_target: None,
+            format: target_format,
+            store: wgpu::StoreOp::Store,
+            source_rect_offset: self.source_rect_offset(queue, source, options),
+        };
+        self.record(encoder, source, target, options);
+    }
+
+    /// Records a pass that draws the source to an attachment of the framebuffer,
+    /// that is found by index, `AttachmentId` or name. Multisampled attachments
+    /// are resolved. The frames of surface attachments are acquired, unless they
+    /// are live already, so remember to `present` after submitting.
+    ///
+    /// With a `target_rect`, a multisampled attachment needs to store its
+    /// multisample texture, otherwise `FramebufferError::MultisampleNotStored`
+    /// is returned.
+    pub fn blit_to_framebuffer(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        source: &wgpu::TextureView,
+        framebuffer: &mut Framebuffer,
+        attachment: impl AttachmentKey,
+        options: &BlitOptions,
+    ) -> Result<(), FramebufferError> {
+        let (view, resolve_target, format, store) =
+            framebuffer.blit_target(attachment, options.target_rect.is_some())?;
+        let target = BlitTarget {
+            view: &view,
+            resolve_target: resolve_target.as_ref(),
+            format,
+            store,
+            source_rect_offset: self.source_rect_offset(queue, source, options),
+        };
+        self.record(encoder, source, target, options);
+        Ok(())
+    }
+
+    /// Records a pass that draws all of the source to all of the target, as `blit`
+    /// without a queue
+    pub(crate) fn blit_whole(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+        target_format: wgpu::TextureFormat,
+        filter: wgpu::FilterMode,
+    ) {
+        let target = BlitTarget {
+            view: target,
+            resolve_target: None,
+            format: target_format,
+            store: wgpu::StoreOp::Store,
+            source_rect_offset: 0,
+        };
+        self.record(
+            encoder,
+            source,
+            target,
+            &BlitOptions::default().filter(filter),
+        );
+    }
+
+    fn source_rect_offset(
+        &mut self,
+        queue: &wgpu::Queue,
+        source: &wgpu::TextureView,
+        options: &BlitOptions,
+    ) -> u32 {
+        let Some(rect) = options.source_rect else {
+            return 0;
+        };
+        let source_size = source.texture().size();
+        let (width, height) = (source_size.width as f32, source_size.height as f32);
+        let rect = [
+            rect.x as f32 / width,
+            rect.y as f32 / height,
+            rect.width as f32 / width,
+            rect.height as f32 / height,
+        ];
+        self.source_rects.offset(&self.device, queue, rect)
+    }
+
+    fn record(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::TextureView,
+        target: BlitTarget,
+        options: &BlitOptions,
+    ) {
+        let sample_count = target.view.texture().sample_count();
+        let pipeline = self
+            .pipelines
+            .entry((target.format, sample_count))
+            .or_insert_with(|| {
+                fullscreen::pipeline(
+                    &self.device,
+                    "Blit pipeline",
+                    &self.pipeline_layout,
+                    &self.shader,
+                    FullscreenTarget {
+                        sample_count,
+                        ..FullscreenTarget::color(target.format)
+                    },
+                )
+            });
+        let sampler = match options.filter {
+            wgpu::FilterMode::Nearest => &self.nearest_sampler,
+            wgpu::FilterMode::Linear => &self.linear_sampler,
+        };
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Blit bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &self.source_rects.buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(SOURCE_RECT_SIZE),
+                    }),
+                },
+            ],
+        });
+
+        // Drawing to a part of the target keeps the rest, also in the
+        // multisampled texture that is resolved as a whole
+        let (load, store) = match options.target_rect {
+            Some(_) => (wgpu::LoadOp::Load, wgpu::StoreOp::Store),
+            None => (wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT), target.store),
+        };
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Blit pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target.view,
+                depth_slice: None,
+                resolve_target: target.resolve_target,
+                ops: wgpu::Operations { load, store },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        if let Some(rect) = options.target_rect {
+            pass.set_viewport(
+                rect.x as f32,
+                rect.y as f32,
+                rect.width as f32,
+                rect.height as f32,
+                0.0,
+                1.0,
+            );
+        }
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[target.source_rect_offset]);
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,414 @@
+/// Pixels of a decoded 8 bit PNG, tightly packed
+pub(crate) struct DecodedPng {
+    pub width: u32,
+    pub height: u32,
+    /// 1 for grayscale, 2 for grayscale with alpha, 3 for RGB and 4 for RGBA
+    pub channels: usize,
+    pub data: Vec<u8>,
+}
+
+/// Decodes a non-interlaced PNG with 8 bits per channel and no palette,
+/// which covers everything `Image::encode` writes. None if the file is
+/// malformed or uses other options.
+pub(crate) fn decode_png(file: &[u8]) -> Option<DecodedPng> {
+    let mut chunks = file.strip_prefix(b"\x89PNG\r\n\x1a\n")?;
+    let mut header = None;
+    let mut compressed = Vec::new();
+    while chunks.len() >= 12 {
+        let len = u32::from_be_bytes(chunks[..4].try_into().ok()?) as usize;
+        let kind = &chunks[4..8];
+        let data = chunks.get(8..8 + len)?;
+        match kind {
+            b"IHDR" => header = Some(data),
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        chunks = chunks.get(12 + len..)?;
+    }
+
+    let header = header.filter(|h| h.len() == 13)?;
+    let width = u32::from_be_bytes(header[0..4].try_into().ok()?);
+    let height = u32::from_be_bytes(header[4..8].try_into().ok()?);
+    let channels = match header[9] {
+        0 => 1,
+        4 => 2,
+        2 => 3,
+        6 => 4,
+        _ => return None,
+    };
+    // 8 bits, deflate, adaptive filtering, no interlace
+    if header[8] != 8 || header[10] != 0 || header[11] != 0 || header[12] != 0 {
+        return None;
+    }
+
+    let filtered = inflate(compressed.get(2..)?)?;
+    let row_bytes = width as usize * channels;
+    if filtered.len() < (row_bytes + 1) * height as usize {
+        return None;
+    }
+
+    let mut data = vec![0u8; row_bytes * height as usize];
+    for y in 0..height as usize {
+        let filter = filtered[y * (row_bytes + 1)];
+        let src = &filtered[y * (row_bytes + 1) + 1..][..row_bytes];
+        let (previous, current) = data.split_at_mut(y * row_bytes);
+        let up = previous.get(previous.len().wrapping_sub(row_bytes)..);
+        let current = &mut current[..row_bytes];
+        for x in 0..row_bytes {
+            let a = if x >= channels {
+                current[x - channels]
+            } else {
+                0
+            };
+            let b = up.map_or(0, |up| up[x]);
+            let c = match up {
+                Some(up) if x >= channels => up[x - channels],
+                _ => 0,
+            };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return None,
+            };
+            current[x] = src[x].wrapping_add(predicted);
+        }
+    }
+
+    Some(DecodedPng {
+        width,
+        height,
+        channels,
+        data,
+    })
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self.data.get(self.pos)?;
+            value |= (((byte >> self.bit) & 1) as u32) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Some(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+/// Canonical Huffman code, decoded one bit at a time
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Option<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Decompresses a raw deflate stream
+fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    let mut reader = BitReader {
+        data,
+        pos: 0,
+        bit: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align_to_byte();
+                let header = data.get(reader.pos..reader.pos + 4)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                if len != !u16::from_le_bytes([header[2], header[3]]) {
+                    return None;
+                }
+                let start = reader.pos + 4;
+                out.extend_from_slice(data.get(start..start + len as usize)?);
+                reader.pos = start + len as usize;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let literals = Huffman::new(&lengths);
+                let distances = Huffman::new(&[5; 30]);
+                inflate_block(&mut reader, &literals, &distances, &mut out)?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &literals, &distances, &mut out)?;
+            }
+            _ => return None,
+        }
+        if last {
+            return Some(out);
+        }
+    }
+}
+
+fn read_dynamic_codes(reader: &mut BitReader) -> Option<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &i in &ORDER[..code_count] {
+        code_lengths[i] = reader.bits(3)? as u8;
+    }
+    let code = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match code.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last()?, 3 + reader.bits(2)?),
+            17 => (0, 3 + reader.bits(3)?),
+            18 => (0, 11 + reader.bits(7)?),
+            _ => return None,
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() != literal_count + distance_count {
+        return None;
+    }
+    Some((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    literals: &Huffman,
+    distances: &Huffman,
+    out: &mut Vec<u8>,
+) -> Option<()> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Some(()),
+            _ => {
+                let i = symbol - 257;
+                let len =
+                    *LENGTH_BASE.get(i)? as usize + reader.bits(LENGTH_EXTRA[i] as u32)? as usize;
+                let d = distances.decode(reader)? as usize;
+                let distance = *DISTANCE_BASE.get(d)? as usize
+                    + reader.bits(DISTANCE_EXTRA[d] as u32)? as usize;
+                let start = out.len().checked_sub(distance)?;
+                // The copy may overlap with what it produces
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Image, ImageFileFormat};
+
+    /// Letters from a linear congruential generator, so that they compress
+    /// with dynamic Huffman codes
+    fn letters(count: usize) -> Vec<u8> {
+        let mut x = 1u32;
+        (0..count)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345) & 0x7fff_ffff;
+                b"aaaabbc d"[(x >> 16) as usize % 9]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn inflate_stored() {
+        let data = b"stored";
+        let mut stream = vec![1, 6, 0, !6, 0xff];
+        stream.extend_from_slice(data);
+        assert_eq!(inflate(&stream).unwrap(), data);
+        // Corrupt length complement
+        stream[3] = 0;
+        assert_eq!(inflate(&stream), None);
+    }
+
+    #[test]
+    fn inflate_fixed_huffman() {
+        // Raw deflate of the text with fixed codes, by zlib
+        let stream = [
+            203, 72, 205, 201, 201, 87, 200, 64, 144, 58, 10, 105, 153, 21, 169, 41, 10, 25, 165,
+            105, 105, 185, 137, 121, 0,
+        ];
+        assert_eq!(
+            inflate(&stream).unwrap(),
+            b"hello hello hello, fixed huffman"
+        );
+    }
+
+    #[test]
+    fn inflate_dynamic_huffman() {
+        // Raw deflate of `letters(64)` with dynamic codes, by zlib
+        let stream = [
+            61, 201, 185, 13, 0, 0, 8, 2, 192, 85, 88, 141, 103, 255, 25, 68, 11, 11, 66, 56, 2,
+            147, 220, 72, 13, 109, 62, 44, 5, 109, 116, 235, 228, 46, 197, 181, 16, 146, 7,
+        ];
+        assert_eq!((stream[0] >> 1) & 3, 2);
+        assert_eq!(inflate(&stream).unwrap(), letters(64));
+    }
+
+    #[test]
+    fn inflate_truncated() {
+        let stream = [203, 72, 205, 201, 201, 87, 200, 64, 144];
+        assert_eq!(inflate(&stream), None);
+    }
+
+    #[test]
+    fn png_filters() {
+        // 3x5 RGB, each row with a different filter type, compressed by zlib
+        let png = [
+            137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 3, 0, 0, 0, 5,
+            8, 2, 0, 0, 0, 15, 19, 193, 245, 0, 0, 0, 47, 73, 68, 65, 84, 120, 218, 99, 96, 224,
+            18, 209, 48, 178, 9, 136, 74, 97, 148, 211, 48, 178, 4, 3, 38, 57, 57, 57, 125, 125,
+            125, 7, 7, 7, 102, 27, 71, 55, 87, 87, 87, 63, 63, 63, 22, 184, 24, 0, 223, 120, 9,
+            101, 168, 69, 185, 187, 0, 0, 0, 0, 73, 69, 78, 68, 174, 66, 96, 130,
+        ];
+        let decoded = decode_png(&png).unwrap();
+        assert_eq!((decoded.width, decoded.height, decoded.channels), (3, 5, 3));
+        let mut expected = Vec::new();
+        for y in 0..5 {
+            for x in 0..3 {
+                for c in 0..3 {
+                    expected.push(((x * 40 + y * 30 + c * 10 + x * y * 17) % 256) as u8);
+                }
+            }
+        }
+        assert_eq!(decoded.data, expected);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        // Large enough for several stored deflate blocks
+        let (width, height) = (200, 120);
+        let data = (0..width * height * 4).map(|i| (i % 251) as u8).collect();
+        let image = Image {
+            width,
+            height,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            data,
+        };
+        let mut png = Vec::new();
+        image.encode(ImageFileFormat::Png, &mut png).unwrap();
+        let decoded = decode_png(&png).unwrap();
+        assert_eq!(
+            (decoded.width, decoded.height, decoded.channels),
+            (width, height, 4)
+        );
+        assert_eq!(decoded.data, image.data);
+    }
+
+    #[test]
+    fn malformed_png() {
+        assert!(decode_png(b"not a png").is_none());
+        let image = Image {
+            width: 2,
+            height: 2,
+            format: wgpu::TextureFormat::R8Unorm,
+            data: vec![1, 2, 3, 4],
+        };
+        let mut png = Vec::new();
+        image.encode(ImageFileFormat::Png, &mut png).unwrap();
+        assert!(decode_png(&png[..png.len() / 2]).is_none());
+    }
+}
@@ -0,0 +1,276 @@
+use crate::{Framebuffer, FramebufferError};
+
+/// Texture color attachment of a `FramebufferDescriptor`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AttachmentDescriptor {
+    /// Name for looking the attachment up, see `Framebuffer::attachment_id`
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub name: Option<String>,
+    pub format: wgpu::TextureFormat,
+    /// Usages in addition to the default ones, see `Framebuffer::add_texture_attachment`
+    #[cfg_attr(feature = "serde", serde(default = "no_usages"))]
+    pub usage: wgpu::TextureUsages,
+    /// Has a full mip chain, see `Framebuffer::set_attachment_mipmapped`
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub mipmapped: bool,
+    /// Keeps the previous frame's texture, see `Framebuffer::add_history_attachment`
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub history: bool,
+    /// Color the passes clear the attachment to
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub clear_color: [f64; 4],
+    /// Keeps the previous contents instead of clearing
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub load: bool,
+    /// If the attachment is stored after the passes, None for the default
+    /// of `Framebuffer::set_attachment_store`
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub store: Option<bool>,
+}
+
+#[cfg(feature = "serde")]
+fn no_usages() -> wgpu::TextureUsages {
+    wgpu::TextureUsages::empty()
+}
+
+impl AttachmentDescriptor {
+    /// Describes an attachment cleared to transparent black
+    pub fn new(format: wgpu::TextureFormat) -> AttachmentDescriptor {
+        AttachmentDescriptor {
+            name: None,
+            format,
+            usage: wgpu::TextureUsages::empty(),
+            mipmapped: false,
+            history: false,
+            clear_color: [0.0; 4],
+            load: false,
+            store: None,
+        }
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    pub fn usage(mut self, usage: wgpu::TextureUsages) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    pub fn mipmapped(mut self, mipmapped: bool) -> Self {
+        self.mipmapped = mipmapped;
+        self
+    }
+
+    pub fn history(mut self, history: bool) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Sets the clear color, and makes it cleared on load
+    pub fn clear_color(mut self, clear_color: [f64; 4]) -> Self {
+        self.clear_color = clear_color;
+        self.load = false;
+        self
+    }
+
+    /// Makes the passes keep the previous contents instead of clearing
+    pub fn load(mut self) -> Self {
+        self.load = true;
+        self
+    }
+
+    pub fn store(mut self, store: bool) -> Self {
+        self.store = Some(store);
+        self
+    }
+}
+
+/// Everything needed for creating a `Framebuffer` with texture attachments,
+/// as one value that can be compared, cloned for creating similar framebuffers,
+/// and with the `serde` feature, loaded from configuration files.
+///
+/// ```rust,no_run
+///    # let device : wgpu::Device = unimplemented!();
+///    use wgpu_misc::{AttachmentDescriptor, Framebuffer, FramebufferDescriptor};
+///    let descriptor = FramebufferDescriptor::new(1280, 720)
+///        .attachment(AttachmentDescriptor::new(wgpu::TextureFormat::Rgba8UnormSrgb).name("albedo"))
+///        .attachment(AttachmentDescriptor::new(wgpu::TextureFormat::Rgba16Float).name("normal"))
+///        .depth_stencil_format(wgpu::TextureFormat::Depth32Float)
+///        .sample_count(4);
+///    let mut gbuffer = Framebuffer::from_descriptor(&descriptor);
+///    gbuffer.configure(&device);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct FramebufferDescriptor {
+    pub width: u32,
+    pub height: u32,
+    pub sample_count: u32,
+    /// Falls back to a supported sample count, see `Framebuffer::set_sample_count_fallback`
+    pub sample_count_fallback: bool,
+    pub layer_count: u32,
+    pub cube: bool,
+    pub attachments: Vec<AttachmentDescriptor>,
+    pub depth_stencil_format: Option<wgpu::TextureFormat>,
+    /// Usages of the depth-stencil texture in addition to `RENDER_ATTACHMENT`
+    pub depth_stencil_usage: wgpu::TextureUsages,
+    /// Depth the passes clear to
+    pub depth_clear_value: f32,
+    /// Stencil value the passes clear to
+    pub stencil_clear_value: u32,
+    pub depth_store: bool,
+    pub stencil_store: bool,
+    pub present_mode: wgpu::PresentMode,
+}
+
+impl Default for FramebufferDescriptor {
+    fn default() -> Self {
+        FramebufferDescriptor {
+            width: 0,
+            height: 0,
+            sample_count: 1,
+            sample_count_fallback: false,
+            layer_count: 1,
+            cube: false,
+            attachments: Vec::new(),
+            depth_stencil_format: None,
+            depth_stencil_usage: wgpu::TextureUsages::empty(),
+            depth_clear_value: 1.0,
+            stencil_clear_value: 0,
+            depth_store: false,
+            stencil_store: false,
+            present_mode: wgpu::PresentMode::AutoVsync,
+        }
+    }
+}
+
+impl FramebufferDescriptor {
+    /// Describes a framebuffer of the resolution, with no attachments
+    pub fn new(width: u32, height: u32) -> FramebufferDescriptor {
+        FramebufferDescriptor {
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    pub fn sample_count_fallback(mut self, fallback: bool) -> Self {
+        self.sample_count_fallback = fallback;
+        self
+    }
+
+    pub fn layer_count(mut self, layer_count: u32) -> Self {
+        self.layer_count = layer_count;
+        self
+    }
+
+    pub fn cube(mut self, cube: bool) -> Self {
+        self.cube = cube;
+        self
+    }
+
+    /// Adds a texture color attachment
+    pub fn attachment(mut self, attachment: AttachmentDescriptor) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+
+    pub fn depth_stencil_format(mut self, format: wgpu::TextureFormat) -> Self {
+        self.depth_stencil_format = Some(format);
+        self
+    }
+
+    pub fn depth_stencil_usage(mut self, usage: wgpu::TextureUsages) -> Self {
+        self.depth_stencil_usage = usage;
+        self
+    }
+
+    pub fn depth_clear_value(mut self, depth: f32) -> Self {
+        self.depth_clear_value = depth;
+        self
+    }
+
+    pub fn stencil_clear_value(mut self, stencil: u32) -> Self {
+        self.stencil_clear_value = stencil;
+        self
+    }
+
+    pub fn depth_store(mut self, store: bool) -> Self {
+        self.depth_store = store;
+        self
+    }
+
+    pub fn stencil_store(mut self, store: bool) -> Self {
+        self.stencil_store = store;
+        self
+    }
+
+    pub fn present_mode(mut self, present_mode: wgpu::PresentMode) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+}
+
+impl Framebuffer {
+    /// Creates a new Framebuffer with the attachments and settings of the descriptor.
+    /// Surface attachments can be added after.
+    pub fn from_descriptor(descriptor: &FramebufferDescriptor) -> Framebuffer {
+        let mut fb = Framebuffer::new();
+        fb.set_resolution(descriptor.width, descriptor.height);
+        fb.set_sample_count(descriptor.sample_count);
+        fb.set_sample_count_fallback(descriptor.sample_count_fallback);
+        fb.set_layer_count(descriptor.layer_count);
+        fb.set_cube(descriptor.cube);
+        for attachment in &descriptor.attachments {
+            fb.add_described_attachment(attachment)
+                .expect("Setting up a texture attachment that was just added");
+        }
+        fb.set_depth_stencil_format(descriptor.depth_stencil_format);
+        fb.set_depth_stencil_usage(descriptor.depth_stencil_usage);
+        fb.set_depth_load_op(wgpu::LoadOp::Clear(descriptor.depth_clear_value));
+        fb.set_stencil_load_op(wgpu::LoadOp::Clear(descriptor.stencil_clear_value));
+        fb.set_depth_store(descriptor.depth_store);
+        fb.set_stencil_store(descriptor.stencil_store);
+        fb.set_present_mode(descriptor.present_mode);
+        fb
+    }
+
+    fn add_described_attachment(
+        &mut self,
+        attachment: &AttachmentDescriptor,
+    ) -> Result<(), FramebufferError> {
+        let id = if attachment.history {
+            self.add_history_attachment(attachment.format, attachment.usage)
+        } else {
+            self.add_texture_attachment_with_usage(attachment.format, attachment.usage)
+        };
+        if let Some(name) = &attachment.name {
+            self.set_attachment_name(id, name)?;
+        }
+        self.set_attachment_mipmapped(id, attachment.mipmapped)?;
+        if attachment.load {
+            self.set_attachment_load_op(id, wgpu::LoadOp::Load)?;
+        } else {
+            self.set_attachment_clear_color(id, &attachment.clear_color)?;
+        }
+        if let Some(store) = attachment.store {
+            self.set_attachment_store(id, store)?;
+        }
+        Ok(())
+    }
+}
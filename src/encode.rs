@@ -0,0 +1,395 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::{FramebufferError, Image};
+
+/// File formats an `Image` can be encoded to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFileFormat {
+    /// PNG, for 8 bits per channel formats
+    Png,
+    /// Binary PPM, for 8 bits per channel formats. Alpha is dropped
+    Ppm,
+    /// Radiance RGBE, for `Rgba16Float` and `Rgba32Float`. Alpha is dropped
+    Hdr,
+}
+
+impl ImageFileFormat {
+    /// Picks the file format from the extension of the path
+    pub fn from_path(path: &Path) -> Option<ImageFileFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFileFormat::Png),
+            "ppm" => Some(ImageFileFormat::Ppm),
+            "hdr" => Some(ImageFileFormat::Hdr),
+            _ => None,
+        }
+    }
+}
+
+impl Image {
+    /// Writes the image to a file, in the format chosen by the file extension
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), FramebufferError> {
+        let path = path.as_ref();
+        let file_format = ImageFileFormat::from_path(path)
+            .ok_or_else(|| FramebufferError::UnsupportedFileExtension(path.to_path_buf()))?;
+        // Encode first, so that unsupported formats don't leave empty files behind
+        let mut encoded = Vec::new();
+        self.encode(file_format, &mut encoded)?;
+        std::fs::write(path, encoded).map_err(FramebufferError::Io)
+    }
+
+    /// Encodes the image in the file format.
+    /// 8 bit pixels are written as is, so sRGB formats stay sRGB encoded.
+    pub fn encode(
+        &self,
+        file_format: ImageFileFormat,
+        writer: &mut impl Write,
+    ) -> Result<(), FramebufferError> {
+        match file_format {
+            ImageFileFormat::Png => self.encode_png(writer),
+            ImageFileFormat::Ppm => self.encode_ppm(writer),
+            ImageFileFormat::Hdr => self.encode_hdr(writer),
+        }
+    }
+
+    fn encode_png(&self, writer: &mut impl Write) -> Result<(), FramebufferError> {
+        let channels = self.rgba8_channels()?;
+        let color_type = match channels {
+            1 => 0, // Grayscale
+            3 => 2, // RGB
+            _ => 6, // RGBA
+        };
+
+        // Each scanline starts with the filter type, 0 for none
+        let row_bytes = self.width as usize * channels;
+        let mut scanlines = Vec::with_capacity((row_bytes + 1) * self.height as usize);
+        for y in 0..self.height {
+            scanlines.push(0);
+            self.rgba8_row(y, channels, &mut scanlines);
+        }
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        header.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+        let io = |result: std::io::Result<()>| result.map_err(FramebufferError::Io);
+        io(writer.write_all(b"\x89PNG\r\n\x1a\n"))?;
+        io(write_png_chunk(writer, b"IHDR", &header))?;
+        io(write_png_chunk(writer, b"IDAT", &zlib_stored(&scanlines)))?;
+        io(write_png_chunk(writer, b"IEND", &[]))
+    }
+
+    fn encode_ppm(&self, writer: &mut impl Write) -> Result<(), FramebufferError> {
+        self.rgba8_channels()?;
+        let mut pixels = Vec::with_capacity(self.width as usize * self.height as usize * 3);
+        for y in 0..self.height {
+            self.rgba8_row(y, 3, &mut pixels);
+        }
+
+        let io = |result: std::io::Result<()>| result.map_err(FramebufferError::Io);
+        io(write!(writer, "P6\n{} {}\n255\n", self.width, self.height))?;
+        io(writer.write_all(&pixels))
+    }
+
+    fn encode_hdr(&self, writer: &mut impl Write) -> Result<(), FramebufferError> {
+        let read_channel: fn(&[u8]) -> f32 = match self.format {
+            wgpu::TextureFormat::Rgba16Float => |b| f16_to_f32(u16::from_le_bytes([b[0], b[1]])),
+            wgpu::TextureFormat::Rgba32Float => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            format => return Err(FramebufferError::UnsupportedFormat(format)),
+        };
+        let channel_bytes = self.bytes_per_pixel() as usize / 4;
+
+        // Flat scanlines, readers tell them apart from run length encoded ones
+        // by the missing 2, 2 marker
+        let mut pixels = Vec::with_capacity(self.width as usize * self.height as usize * 4);
+        for y in 0..self.height {
+            for pixel in self.row(y).chunks_exact(channel_bytes * 4) {
+                let mut rgb = [0.0; 3];
+                for (c, value) in rgb.iter_mut().enumerate() {
+                    *value = read_channel(&pixel[c * channel_bytes..]);
+                }
+                pixels.extend_from_slice(&to_rgbe(rgb));
+            }
+        }
+
+        let io = |result: std::io::Result<()>| result.map_err(FramebufferError::Io);
+        io(write!(
+            writer,
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            self.height, self.width
+        ))?;
+        io(writer.write_all(&pixels))
+    }
+
+    /// Returns the number of channels an 8 bit format is written with
+    fn rgba8_channels(&self) -> Result<usize, FramebufferError> {
+        match self.format {
+            wgpu::TextureFormat::R8Unorm => Ok(1),
+            wgpu::TextureFormat::Rg8Unorm => Ok(3),
+            wgpu::TextureFormat::Rgba8Unorm
+            | wgpu::TextureFormat::Rgba8UnormSrgb
+            | wgpu::TextureFormat::Bgra8Unorm
+            | wgpu::TextureFormat::Bgra8UnormSrgb => Ok(4),
+            format => Err(FramebufferError::UnsupportedFormat(format)),
+        }
+    }
+
+    /// Appends row `y` as `channels` channels per pixel, in RGBA order
+    fn rgba8_row(&self, y: u32, channels: usize, out: &mut Vec<u8>) {
+        let bpp = self.bytes_per_pixel() as usize;
+        let bgra = matches!(
+            self.format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        for pixel in self.row(y).chunks_exact(bpp) {
+            let mut rgba = [0, 0, 0, 255];
+            rgba[..bpp].copy_from_slice(pixel);
+            if bgra {
+                rgba.swap(0, 2);
+            }
+            if channels == 1 {
+                out.push(rgba[0]);
+            } else {
+                out.extend_from_slice(&rgba[..channels]);
+            }
+        }
+    }
+}
+
+fn write_png_chunk(writer: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    let crc = crc32(crc32(!0, kind), data);
+    writer.write_all(&(!crc).to_be_bytes())
+}
+
+/// Wraps the data in a zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: usize = 0xffff;
+    let mut out = Vec::with_capacity(data.len() + data.len() / MAX_BLOCK * 5 + 11);
+    // Deflate with a 32K window, no preset dictionary
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Updates a CRC-32 (ISO-HDLC, as used by PNG) without the final inversion
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // Sums of 5552 bytes can't overflow before the modulo
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+/// Converts linear RGB to the shared exponent encoding of Radiance HDR
+fn to_rgbe(rgb: [f32; 3]) -> [u8; 4] {
+    let max = rgb[0].max(rgb[1]).max(rgb[2]);
+    if max <= 1e-32 || !max.is_finite() {
+        return [0; 4];
+    }
+    // max = mantissa * 2^exponent, mantissa in [0.5, 1)
+    let mut exponent = max.log2().floor() as i32 + 1;
+    if max / 2f32.powi(exponent) >= 1.0 {
+        exponent += 1;
+    }
+    let scale = 256.0 / 2f32.powi(exponent);
+    let channel = |v: f32| (v.max(0.0) * scale).min(255.0) as u8;
+    [
+        channel(rgb[0]),
+        channel(rgb[1]),
+        channel(rgb[2]),
+        (exponent + 128).clamp(0, 255) as u8,
+    ]
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = ((half >> 15) as u32) << 31;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x3ff) as u32;
+    let bits = match (exponent, mantissa) {
+        (0, 0) => sign,
+        (0, _) => {
+            // Subnormal, exactly representable as mantissa * 2^-24
+            let value = mantissa as f32 / (1 << 24) as f32;
+            return if sign != 0 { -value } else { value };
+        }
+        (0x1f, _) => sign | 0x7f80_0000 | (mantissa << 13),
+        _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(format: wgpu::TextureFormat, width: u32, height: u32, data: Vec<u8>) -> Image {
+        Image {
+            width,
+            height,
+            format,
+            data,
+        }
+    }
+
+    #[test]
+    fn file_format_from_extension() {
+        let format = |path: &str| ImageFileFormat::from_path(Path::new(path));
+        assert_eq!(format("a.png"), Some(ImageFileFormat::Png));
+        assert_eq!(format("dir/a.PPM"), Some(ImageFileFormat::Ppm));
+        assert_eq!(format("a.hdr"), Some(ImageFileFormat::Hdr));
+        assert_eq!(format("a.jpg"), None);
+        assert_eq!(format("a"), None);
+    }
+
+    #[test]
+    fn save_unknown_extension() {
+        let image = image(wgpu::TextureFormat::Rgba8Unorm, 1, 1, vec![0; 4]);
+        let result = image.save("image.jpg");
+        assert!(matches!(
+            result,
+            Err(FramebufferError::UnsupportedFileExtension(path)) if path == Path::new("image.jpg")
+        ));
+    }
+
+    #[test]
+    fn ppm_header_and_pixels() {
+        let pixels = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        let mut ppm = Vec::new();
+        image(wgpu::TextureFormat::Rgba8Unorm, 2, 1, pixels.clone())
+            .encode(ImageFileFormat::Ppm, &mut ppm)
+            .unwrap();
+        assert_eq!(ppm, b"P6\n2 1\n255\n\x01\x02\x03\x05\x06\x07");
+
+        // BGRA is swapped to RGB
+        let mut ppm = Vec::new();
+        image(wgpu::TextureFormat::Bgra8UnormSrgb, 1, 2, pixels)
+            .encode(ImageFileFormat::Ppm, &mut ppm)
+            .unwrap();
+        assert_eq!(ppm, b"P6\n1 2\n255\n\x03\x02\x01\x07\x06\x05");
+    }
+
+    #[test]
+    fn unsupported_formats() {
+        let float = image(wgpu::TextureFormat::Rgba16Float, 1, 1, vec![0; 8]);
+        assert!(matches!(
+            float.encode(ImageFileFormat::Png, &mut Vec::new()),
+            Err(FramebufferError::UnsupportedFormat(
+                wgpu::TextureFormat::Rgba16Float
+            ))
+        ));
+        let unorm = image(wgpu::TextureFormat::Rgba8Unorm, 1, 1, vec![0; 4]);
+        assert!(matches!(
+            unorm.encode(ImageFileFormat::Hdr, &mut Vec::new()),
+            Err(FramebufferError::UnsupportedFormat(
+                wgpu::TextureFormat::Rgba8Unorm
+            ))
+        ));
+    }
+
+    #[test]
+    fn half_floats() {
+        assert_eq!(f16_to_f32(0x0000), 0.0);
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x3800), 0.5);
+        assert_eq!(f16_to_f32(0x7bff), 65504.0);
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+        assert!(f16_to_f32(0x7e00).is_nan());
+    }
+
+    #[test]
+    fn rgbe() {
+        assert_eq!(to_rgbe([1.0, 0.5, 0.25]), [128, 64, 32, 129]);
+        assert_eq!(to_rgbe([0.5, 0.0, 0.0]), [128, 0, 0, 128]);
+        assert_eq!(to_rgbe([3.0, 1.0, 0.0]), [192, 64, 0, 130]);
+        // Negative channels are clamped, black and non-finite values are zero
+        assert_eq!(to_rgbe([1.0, -1.0, 0.0]), [128, 0, 0, 129]);
+        assert_eq!(to_rgbe([0.0; 3]), [0; 4]);
+        assert_eq!(to_rgbe([f32::INFINITY, 0.0, 0.0]), [0; 4]);
+    }
+
+    #[test]
+    fn hdr_header_and_pixels() {
+        // 1.0, 0.5, 0.25 and 1.0 alpha, as halfs
+        let pixel = [0x3c00u16, 0x3800, 0x3400, 0x3c00];
+        let data = pixel.iter().flat_map(|c| c.to_le_bytes()).collect();
+        let mut hdr = Vec::new();
+        image(wgpu::TextureFormat::Rgba16Float, 1, 1, data)
+            .encode(ImageFileFormat::Hdr, &mut hdr)
+            .unwrap();
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 1\n";
+        assert_eq!(&hdr[..header.len()], header);
+        assert_eq!(&hdr[header.len()..], [128, 64, 32, 129]);
+    }
+
+    #[cfg(all(feature = "testing", not(target_arch = "wasm32")))]
+    #[test]
+    fn png_round_trip() {
+        use crate::decode::decode_png;
+
+        let cases = [
+            (wgpu::TextureFormat::R8Unorm, 1),
+            (wgpu::TextureFormat::Rg8Unorm, 3),
+            (wgpu::TextureFormat::Rgba8UnormSrgb, 4),
+            (wgpu::TextureFormat::Bgra8Unorm, 4),
+        ];
+        for (format, channels) in cases {
+            let (width, height) = (5, 3);
+            let bpp = format.block_copy_size(None).unwrap();
+            let data = (0..width * height * bpp).map(|i| (i * 7) as u8).collect();
+            let image = image(format, width, height, data);
+            let mut png = Vec::new();
+            image.encode(ImageFileFormat::Png, &mut png).unwrap();
+
+            let decoded = decode_png(&png).unwrap();
+            assert_eq!((decoded.width, decoded.height), (width, height));
+            assert_eq!(decoded.channels, channels);
+            let mut expected = Vec::new();
+            for y in 0..height {
+                image.rgba8_row(y, channels, &mut expected);
+            }
+            assert_eq!(decoded.data, expected, "{format:?}");
+        }
+    }
+}
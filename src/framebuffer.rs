@@ -1,28 +1,332 @@
+use std::collections::HashMap;
+
+use crate::Image;
+use crate::blit::Blitter;
+use crate::mipmap::MipGenerator;
+use crate::readback::{QueryStaging, Staging};
+use crate::timestamps::TimestampQueries;
+
+/// Errors reported by the fallible `Framebuffer` operations
+#[derive(Debug)]
+pub enum FramebufferError {
+    /// Creating a surface for the window failed
+    CreateSurface(wgpu::CreateSurfaceError),
+    /// Acquiring the next texture from a surface failed
+    SurfaceAcquire(wgpu::SurfaceError),
+    /// The framebuffer was modified, but `configure` was not called after
+    NotConfigured,
+    /// The resolution has zero width or height
+    ZeroSize,
+    /// The resolution or the size the passes render at is over the maximum
+    /// texture dimension of the device
+    TooLarge,
+    /// The format can't be used for the attachment on this device
+    UnsupportedFormat(wgpu::TextureFormat),
+    /// The attachment has no texture, as it renders to a surface or isn't configured
+    NoTexture,
+    /// The texture was not created with the usage the operation needs
+    MissingUsage(wgpu::TextureUsages),
+    /// Mapping a readback buffer failed
+    BufferMap(wgpu::BufferAsyncError),
+    /// Waiting for the device failed
+    DevicePoll(wgpu::PollError),
+    /// Writing a file failed
+    Io(std::io::Error),
+    /// The file extension is not one of the image file formats
+    UnsupportedFileExtension(std::path::PathBuf),
+    /// The surface can't be presented from the adapter
+    SurfaceUnsupported,
+    /// The layer count is zero, over the device limit, or more than one with surface
+    /// attachments, or not a multiple of 6 or with a non-square resolution for cubemaps
+    InvalidLayers,
+    /// The layer is not less than the layer count
+    LayerOutOfRange(u32),
+    /// There are more color attachments than the device supports
+    TooManyAttachments,
+    /// A blit to a part of a multisampled attachment would load the multisample
+    /// texture, that the passes don't store, see `Framebuffer::set_attachment_store`
+    MultisampleNotStored,
+    /// The framebuffer doesn't have the single color attachment the operation
+    /// needs, holding the count it has
+    AttachmentCount(usize),
+    /// The query is not less than the occlusion query count
+    QueryOutOfRange(u32),
+    /// The sample count is not supported by the formats of all the attachments,
+    /// see `Framebuffer::supported_sample_counts`
+    UnsupportedSampleCount(u32),
+    /// No attachment has the index, `AttachmentId` or name
+    UnknownAttachment,
+    /// The attachment is a texture, but the operation needs a surface
+    NotASurface,
+    /// The present mode is not supported by the surface
+    UnsupportedPresentMode(wgpu::PresentMode),
+    /// The composite alpha mode is not supported by the surface
+    UnsupportedAlphaMode(wgpu::CompositeAlphaMode),
+}
+
+impl FramebufferError {
+    /// Returns true if the error only means that the current frame should be
+    /// skipped, such as a timeout when acquiring the surface texture.
+    /// Nothing is left live, so rendering can continue with the next frame.
+    pub fn should_skip_frame(&self) -> bool {
+        matches!(
+            self,
+            FramebufferError::SurfaceAcquire(wgpu::SurfaceError::Timeout)
+        )
+    }
+}
+
+impl std::fmt::Display for FramebufferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FramebufferError::CreateSurface(err) => write!(f, "Surface creation failed: {err}"),
+            FramebufferError::SurfaceAcquire(err) => {
+                write!(f, "Acquiring the next surface texture failed: {err}")
+            }
+            FramebufferError::NotConfigured => {
+                write!(f, "Framebuffer was modified but not reconfigured")
+            }
+            FramebufferError::ZeroSize => write!(f, "Framebuffer resolution has a zero dimension"),
+            FramebufferError::TooLarge => {
+                write!(
+                    f,
+                    "Framebuffer size is over the device's maximum texture dimension"
+                )
+            }
+            FramebufferError::UnsupportedFormat(format) => {
+                write!(f, "Format {format:?} is not supported for the attachment")
+            }
+            FramebufferError::NoTexture => write!(f, "Attachment has no texture"),
+            FramebufferError::MissingUsage(usage) => {
+                write!(f, "Texture is missing the usage {usage:?}")
+            }
+            FramebufferError::BufferMap(err) => write!(f, "Buffer mapping failed: {err}"),
+            FramebufferError::DevicePoll(err) => write!(f, "Device poll failed: {err}"),
+            FramebufferError::Io(err) => write!(f, "I/O error: {err}"),
+            FramebufferError::UnsupportedFileExtension(path) => write!(
+                f,
+                "File {} doesn't have a png, ppm or hdr extension",
+                path.display()
+            ),
+            FramebufferError::SurfaceUnsupported => {
+                write!(f, "Surface is not supported by the adapter")
+            }
+            FramebufferError::InvalidLayers => write!(f, "Invalid layer configuration"),
+            FramebufferError::LayerOutOfRange(layer) => {
+                write!(f, "Layer {layer} is out of range")
+            }
+            FramebufferError::TooManyAttachments => {
+                write!(f, "Too many color attachments for the device")
+            }
+            FramebufferError::QueryOutOfRange(query) => {
+                write!(f, "Query {query} is out of range")
+            }
+            FramebufferError::UnsupportedSampleCount(count) => {
+                write!(
+                    f,
+                    "Sample count {count} is not supported by the attachments"
+                )
+            }
+            FramebufferError::AttachmentCount(count) => {
+                write!(
+                    f,
+                    "Expected a single color attachment, but there are {count}"
+                )
+            }
+            FramebufferError::MultisampleNotStored => {
+                write!(f, "Multisample texture of the attachment is not stored")
+            }
+            FramebufferError::UnknownAttachment => write!(f, "No such attachment"),
+            FramebufferError::NotASurface => write!(f, "Attachment is not a surface"),
+            FramebufferError::UnsupportedPresentMode(mode) => {
+                write!(f, "Present mode {mode:?} is not supported by the surface")
+            }
+            FramebufferError::UnsupportedAlphaMode(mode) => {
+                write!(f, "Alpha mode {mode:?} is not supported by the surface")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FramebufferError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FramebufferError::CreateSurface(err) => Some(err),
+            FramebufferError::SurfaceAcquire(err) => Some(err),
+            FramebufferError::BufferMap(err) => Some(err),
+            FramebufferError::DevicePoll(err) => Some(err),
+            FramebufferError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Options for configuring a surface attachment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SurfaceOptions {
+    /// Usages in addition to `RENDER_ATTACHMENT`, such as `COPY_SRC` or `STORAGE_BINDING`
+    pub usage: wgpu::TextureUsages,
+    /// How the surface is composited with the alpha channel, such as
+    /// `PreMultiplied` for transparent windows
+    pub alpha_mode: wgpu::CompositeAlphaMode,
+    /// Format the render passes view the surface textures in, and pipelines target.
+    /// It can only differ from the surface format by the sRGB suffix, so a
+    /// non-sRGB surface can be rendered to through an sRGB view.
+    /// None for the surface format
+    pub view_format: Option<wgpu::TextureFormat>,
+    /// Number of frames that can be queued, lower for lower latency
+    pub desired_maximum_frame_latency: u32,
+}
+
+impl Default for SurfaceOptions {
+    fn default() -> Self {
+        SurfaceOptions {
+            usage: wgpu::TextureUsages::empty(),
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_format: None,
+            desired_maximum_frame_latency: 2,
+        }
+    }
+}
+
+/// Handle of a color attachment, returned when it is added
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AttachmentId(usize);
+
+impl AttachmentId {
+    /// Returns the index of the attachment, its position in the passes
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// Refers to a color attachment in lookups: its index, `AttachmentId` or name
+pub trait AttachmentKey {
+    /// Returns the index of the attachment, None if there is no such attachment
+    fn attachment_index(&self, framebuffer: &Framebuffer) -> Option<usize>;
+}
+
+impl AttachmentKey for usize {
+    fn attachment_index(&self, framebuffer: &Framebuffer) -> Option<usize> {
+        (*self < framebuffer.color_attachment_count()).then_some(*self)
+    }
+}
+
+impl AttachmentKey for AttachmentId {
+    fn attachment_index(&self, framebuffer: &Framebuffer) -> Option<usize> {
+        self.0.attachment_index(framebuffer)
+    }
+}
+
+impl AttachmentKey for &str {
+    fn attachment_index(&self, framebuffer: &Framebuffer) -> Option<usize> {
+        framebuffer.attachment_id(self).map(AttachmentId::index)
+    }
+}
+
 #[derive(Debug)]
 struct ColorAttachment {
     data: ColorAttachmentData,
     color_format: wgpu::TextureFormat,
+    name: Option<String>,
     configured: Option<ColorAttachmentConfigured>,
-    clear_color: [f64; 4],
+    load_op: wgpu::LoadOp<wgpu::Color>,
+    /// None for storing only when there is no multisample resolve
+    store: Option<wgpu::StoreOp>,
+}
+
+impl ColorAttachment {
+    fn new(data: ColorAttachmentData, color_format: wgpu::TextureFormat) -> ColorAttachment {
+        ColorAttachment {
+            data,
+            color_format,
+            name: None,
+            configured: None,
+            load_op: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+            store: None,
+        }
+    }
+
+    /// Format the render passes use for the attachment
+    fn view_format(&self) -> wgpu::TextureFormat {
+        match &self.data {
+            ColorAttachmentData::Surface { options, .. } => {
+                options.view_format.unwrap_or(self.color_format)
+            }
+            ColorAttachmentData::Texture { .. } => self.color_format,
+        }
+    }
 }
 
 #[derive(Debug)]
 struct ColorAttachmentConfigured {
-    //multisample_texture: Option<wgpu::Texture>,
-    attachment_view: Option<wgpu::TextureView>,
-    resolve_view: Option<wgpu::TextureView>,
+    msaa_texture: Option<wgpu::Texture>,
+    /// Multisampled view the passes of every layer render to, and resolve from
+    msaa_view: Option<wgpu::TextureView>,
+    /// View of all the layers of the output texture. For surfaces it is the
+    /// texture rendered at the render scale, and None without scaling.
+    output_view: Option<wgpu::TextureView>,
+    /// Single layer views of the output texture, that passes render or resolve to
+    layer_views: Vec<wgpu::TextureView>,
+    /// Views of the previous frame's texture of history attachments,
+    /// swapped with the ones above by `swap_history`
+    previous_output_view: Option<wgpu::TextureView>,
+    previous_layer_views: Vec<wgpu::TextureView>,
 }
 #[derive(Debug)]
 enum ColorAttachmentData {
     Surface {
         surface: wgpu::Surface<'static>,
         frame: Option<wgpu::SurfaceTexture>,
+        /// Last configuration, for reconfiguring outdated or lost surfaces
+        config: Option<wgpu::SurfaceConfiguration>,
+        options: SurfaceOptions,
     },
     Texture {
         color_texture: Option<wgpu::Texture>,
+        /// Usages requested in addition to the default ones
+        usage: wgpu::TextureUsages,
+        /// Has a full mip chain, see `generate_mips`
+        mipmapped: bool,
+        /// The texture of the previous frame, for history attachments
+        previous_texture: Option<wgpu::Texture>,
+        /// Keeps the previous frame's texture, see `add_history_attachment`
+        history: bool,
     },
 }
 
+#[derive(Debug)]
+struct DepthStencilConfigured {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    depth_view: Option<wgpu::TextureView>,
+    stencil_view: Option<wgpu::TextureView>,
+    /// Single layer views for the passes. Multisampled textures have just one
+    /// layer, that is used for every pass.
+    layer_views: Vec<wgpu::TextureView>,
+}
+
+/// Views of the color attachments of a render pass to a layer, retained
+/// until the resources change
+#[derive(Debug)]
+struct PreparedPass {
+    color_attachments: Vec<PreparedColorAttachment>,
+}
+
+#[derive(Debug)]
+struct PreparedColorAttachment {
+    /// The view rendered to, None for the live surface frame
+    view: Option<wgpu::TextureView>,
+    /// The view resolved to, None for the live surface frame when `resolve` is set
+    resolve_target: Option<wgpu::TextureView>,
+    resolve: bool,
+    /// Takes the next live surface frame
+    surface: bool,
+}
+
+/// The most color attachments a pass can have, `max_color_attachments` is at most this
+const MAX_COLOR_ATTACHMENTS: usize = 8;
+
 #[derive(Debug)]
 struct LiveFrame {
     view: wgpu::TextureView,
@@ -32,17 +336,49 @@ struct LiveFrame {
 #[derive(Debug)]
 pub struct Framebuffer {
     resolution: (u32, u32),
+    render_scale: f32,
+    upscale_filter: wgpu::FilterMode,
+    /// Sample count in use, the requested one unless it fell back to a lower one
     sample_count: u32,
+    requested_sample_count: u32,
+    /// Falls back to a supported sample count, see `set_sample_count_fallback`
+    sample_count_fallback: bool,
+    /// If the passes resolve the multisampled attachments, see `set_msaa_resolve`
+    msaa_resolve: bool,
+    layer_count: u32,
+    cube: bool,
 
     color_attachments: Vec<ColorAttachment>,
     depth_stencil_format: Option<wgpu::TextureFormat>,
-    depth_stencil_view: Option<wgpu::TextureView>,
+    /// Usages of the depth-stencil texture in addition to `RENDER_ATTACHMENT`
+    depth_stencil_usage: wgpu::TextureUsages,
+    depth_stencil: Option<DepthStencilConfigured>,
 
     live_frame: Vec<LiveFrame>,
     present_mode: wgpu::PresentMode,
+    /// Prepared passes by layer and if they resolve, see `set_msaa_resolve`
+    prepared_passes: HashMap<(u32, bool), PreparedPass>,
 
     depth_store: wgpu::StoreOp,
     depth_load_op: wgpu::LoadOp<f32>,
+    stencil_store: wgpu::StoreOp,
+    stencil_load_op: wgpu::LoadOp<u32>,
+
+    /// Adapter for querying the format features, see `set_adapter`
+    adapter: Option<wgpu::Adapter>,
+    /// Device of the last `configure`, for recovering lost surfaces
+    device: Option<wgpu::Device>,
+    /// Created on the first `generate_mips`
+    mip_generator: Option<MipGenerator>,
+    /// Created on the first `upscale`
+    blitter: Option<Blitter>,
+    /// Passes per frame that get timestamps, 0 for none
+    timestamp_pass_count: u32,
+    /// Created on `configure` when timestamp queries are supported
+    timestamps: Option<TimestampQueries>,
+    occlusion_query_count: u32,
+    /// Created on `configure` when the count is not 0
+    occlusion_query_set: Option<wgpu::QuerySet>,
 
     dirty: bool,
 }
@@ -81,26 +417,57 @@ impl Framebuffer {
     pub fn new() -> Framebuffer {
         Framebuffer {
             color_attachments: Vec::new(),
-            depth_stencil_view: None,
+            depth_stencil_usage: wgpu::TextureUsages::empty(),
+            depth_stencil: None,
             live_frame: Vec::new(),
+            prepared_passes: HashMap::new(),
             sample_count: 1,
+            requested_sample_count: 1,
+            sample_count_fallback: false,
+            msaa_resolve: true,
+            layer_count: 1,
+            cube: false,
             resolution: (0, 0),
+            render_scale: 1.0,
+            upscale_filter: wgpu::FilterMode::Linear,
             depth_stencil_format: None,
             present_mode: wgpu::PresentMode::AutoVsync,
+            adapter: None,
+            device: None,
+            mip_generator: None,
+            blitter: None,
+            timestamp_pass_count: 0,
+            timestamps: None,
+            occlusion_query_count: 0,
+            occlusion_query_set: None,
             dirty: true,
             depth_store: wgpu::StoreOp::Discard,
             depth_load_op: wgpu::LoadOp::Clear(1.0),
+            stencil_store: wgpu::StoreOp::Discard,
+            stencil_load_op: wgpu::LoadOp::Clear(0),
         }
     }
 
     /// Creates a new Framebuffer that renders to the surface of a window
+    /// Panics if the surface can't be created, see `try_new_from_window`
     pub fn new_from_window<W: wgpu::WindowHandle + 'static>(
         instance: &wgpu::Instance,
         window: std::sync::Arc<W>,
         color_format: wgpu::TextureFormat,
     ) -> Framebuffer {
-        let surface = instance.create_surface(window).expect("Surface creation");
-        Self::new_from_surface(surface, color_format)
+        Self::try_new_from_window(instance, window, color_format).expect("Surface creation")
+    }
+
+    /// Creates a new Framebuffer that renders to the surface of a window
+    pub fn try_new_from_window<W: wgpu::WindowHandle + 'static>(
+        instance: &wgpu::Instance,
+        window: std::sync::Arc<W>,
+        color_format: wgpu::TextureFormat,
+    ) -> Result<Framebuffer, FramebufferError> {
+        let surface = instance
+            .create_surface(window)
+            .map_err(FramebufferError::CreateSurface)?;
+        Ok(Self::new_from_surface(surface, color_format))
     }
 
     /// Create a new Framebuffer that renders to a surface
@@ -113,6 +480,72 @@ impl Framebuffer {
         fb
     }
 
+    /// Creates a new Framebuffer that renders to the surface of a window, with the
+    /// format, present mode and alpha mode picked as in `new_from_surface_negotiated`
+    pub fn try_new_from_window_negotiated<W: wgpu::WindowHandle + 'static>(
+        instance: &wgpu::Instance,
+        window: std::sync::Arc<W>,
+        adapter: &wgpu::Adapter,
+        present_modes: &[wgpu::PresentMode],
+        alpha_modes: &[wgpu::CompositeAlphaMode],
+    ) -> Result<Framebuffer, FramebufferError> {
+        let surface = instance
+            .create_surface(window)
+            .map_err(FramebufferError::CreateSurface)?;
+        Self::new_from_surface_negotiated(surface, adapter, present_modes, alpha_modes)
+    }
+
+    /// Creates a new Framebuffer that renders to a surface, with the format,
+    /// present mode and alpha mode picked from the surface capabilities on the adapter.
+    ///
+    /// The format is the first sRGB format the surface prefers, or its most preferred one
+    /// if there are none. The present mode is the first supported one in `present_modes`,
+    /// such as `[Mailbox, FifoRelaxed, Fifo]`, falling back to `Fifo` that is always supported.
+    /// The alpha mode is the first supported one in `alpha_modes`, such as
+    /// `[PreMultiplied, PostMultiplied]` for transparent windows, falling back to the
+    /// one the surface prefers.
+    /// The choices can be queried with `attachment_format`, `present_mode` and `alpha_mode`.
+    /// The adapter is also set as with `set_adapter`.
+    pub fn new_from_surface_negotiated(
+        surface: wgpu::Surface<'static>,
+        adapter: &wgpu::Adapter,
+        present_modes: &[wgpu::PresentMode],
+        alpha_modes: &[wgpu::CompositeAlphaMode],
+    ) -> Result<Framebuffer, FramebufferError> {
+        let caps = surface.get_capabilities(adapter);
+        let color_format = caps
+            .formats
+            .iter()
+            .copied()
+            .find(|format| format.is_srgb())
+            .or(caps.formats.first().copied())
+            .ok_or(FramebufferError::SurfaceUnsupported)?;
+        let present_mode = present_modes
+            .iter()
+            .copied()
+            .find(|mode| caps.present_modes.contains(mode))
+            .unwrap_or(wgpu::PresentMode::Fifo);
+        let alpha_mode = alpha_modes
+            .iter()
+            .copied()
+            .find(|mode| caps.alpha_modes.contains(mode))
+            .or(caps.alpha_modes.first().copied())
+            .unwrap_or(wgpu::CompositeAlphaMode::Auto);
+
+        let mut fb = Framebuffer::new();
+        fb.add_surface_attachment_with_options(
+            surface,
+            color_format,
+            SurfaceOptions {
+                alpha_mode,
+                ..Default::default()
+            },
+        );
+        fb.set_adapter(adapter);
+        fb.set_present_mode(present_mode);
+        Ok(fb)
+    }
+
     /// Create a new Framebuffer that renders to a texture
     pub fn new_with_texture(color_format: wgpu::TextureFormat) -> Framebuffer {
         let mut fb = Framebuffer::new();
@@ -125,30 +558,230 @@ impl Framebuffer {
         &mut self,
         surface: wgpu::Surface<'static>,
         color_format: wgpu::TextureFormat,
-    ) {
-        self.color_attachments.push(ColorAttachment {
-            data: ColorAttachmentData::Surface {
+    ) -> AttachmentId {
+        self.add_surface_attachment_with_options(surface, color_format, SurfaceOptions::default())
+    }
+
+    /// Adds a color attachment that renders to a surface, configured with the options
+    pub fn add_surface_attachment_with_options(
+        &mut self,
+        surface: wgpu::Surface<'static>,
+        color_format: wgpu::TextureFormat,
+        options: SurfaceOptions,
+    ) -> AttachmentId {
+        self.color_attachments.push(ColorAttachment::new(
+            ColorAttachmentData::Surface {
                 surface,
                 frame: None,
+                config: None,
+                options,
             },
             color_format,
-            configured: None,
-            clear_color: [0.0, 0.0, 0.0, 0.0f64],
-        });
+        ));
+        self.dirty = true;
+        AttachmentId(self.color_attachments.len() - 1)
+    }
+
+    /// Sets the options of a surface attachment.
+    /// Invalidates resources, requires `configure`
+    pub fn set_surface_options(
+        &mut self,
+        attachment: impl AttachmentKey,
+        surface_options: SurfaceOptions,
+    ) -> Result<(), FramebufferError> {
+        let attachment = self.color_attachment_mut(attachment)?;
+        match &mut attachment.data {
+            ColorAttachmentData::Surface { options, .. } => *options = surface_options,
+            ColorAttachmentData::Texture { .. } => return Err(FramebufferError::NotASurface),
+        }
+        attachment.configured = None;
         self.dirty = true;
+        Ok(())
+    }
+
+    /// Returns the alpha mode of a surface attachment, None for texture attachments
+    pub fn alpha_mode(&self, attachment: impl AttachmentKey) -> Option<wgpu::CompositeAlphaMode> {
+        self.surface_options(attachment)
+            .map(|options| options.alpha_mode)
+    }
+
+    /// Returns the options of a surface attachment, None for texture attachments
+    pub fn surface_options(&self, attachment: impl AttachmentKey) -> Option<&SurfaceOptions> {
+        let idx = attachment.attachment_index(self)?;
+        match &self.color_attachments[idx].data {
+            ColorAttachmentData::Surface { options, .. } => Some(options),
+            ColorAttachmentData::Texture { .. } => None,
+        }
     }
 
     /// Adds a color attachment that renders to a texture
-    pub fn add_texture_attachment(&mut self, color_format: wgpu::TextureFormat) {
-        self.color_attachments.push(ColorAttachment {
-            data: ColorAttachmentData::Texture {
+    ///
+    /// The texture is created with `RENDER_ATTACHMENT` and `TEXTURE_BINDING` usages,
+    /// and `STORAGE_BINDING` when the format supports it.
+    pub fn add_texture_attachment(&mut self, color_format: wgpu::TextureFormat) -> AttachmentId {
+        self.add_texture_attachment_with_usage(color_format, wgpu::TextureUsages::empty())
+    }
+
+    /// Adds a color attachment that renders to a texture, with additional usages
+    /// such as `COPY_SRC` for reading it back
+    pub fn add_texture_attachment_with_usage(
+        &mut self,
+        color_format: wgpu::TextureFormat,
+        usage: wgpu::TextureUsages,
+    ) -> AttachmentId {
+        self.color_attachments.push(ColorAttachment::new(
+            ColorAttachmentData::Texture {
                 color_texture: None,
+                usage,
+                mipmapped: false,
+                previous_texture: None,
+                history: false,
             },
             color_format,
-            configured: None,
-            clear_color: [0.0, 0.0, 0.0, 0.0f64],
-        });
+        ));
+        self.dirty = true;
+        AttachmentId(self.color_attachments.len() - 1)
+    }
+
+    /// Adds a color attachment with two textures, the current one the passes render
+    /// to and the one of the previous frame, swapped by `swap_history`. For temporal
+    /// effects such as TAA, motion blur and feedback, that read the last frame's result
+    /// with `previous_view`.
+    pub fn add_history_attachment(
+        &mut self,
+        color_format: wgpu::TextureFormat,
+        usage: wgpu::TextureUsages,
+    ) -> AttachmentId {
+        let id = self.add_texture_attachment_with_usage(color_format, usage);
+        if let ColorAttachmentData::Texture { history, .. } =
+            &mut self.color_attachments[id.index()].data
+        {
+            *history = true;
+        }
+        id
+    }
+
+    /// Swaps the current and previous textures of the history attachments.
+    /// Call it once per frame, before the passes. The previous textures are
+    /// cleared when configured, so they are black on the first frame.
+    pub fn swap_history(&mut self) {
+        let mut swapped = false;
+        for attachment in &mut self.color_attachments {
+            if let ColorAttachmentData::Texture {
+                color_texture,
+                previous_texture,
+                history: true,
+                ..
+            } = &mut attachment.data
+            {
+                std::mem::swap(color_texture, previous_texture);
+                swapped = true;
+                if let Some(configured) = &mut attachment.configured {
+                    std::mem::swap(
+                        &mut configured.output_view,
+                        &mut configured.previous_output_view,
+                    );
+                    std::mem::swap(
+                        &mut configured.layer_views,
+                        &mut configured.previous_layer_views,
+                    );
+                }
+            }
+        }
+        if swapped {
+            // The passes have the views of the swapped textures
+            self.prepared_passes.clear();
+        }
+    }
+
+    /// Adds a color attachment that renders to a texture, that can be looked up
+    /// by the name, such as `"albedo"` or `"normal"`
+    pub fn add_named_texture_attachment(
+        &mut self,
+        name: &str,
+        color_format: wgpu::TextureFormat,
+        usage: wgpu::TextureUsages,
+    ) -> AttachmentId {
+        let id = self.add_texture_attachment_with_usage(color_format, usage);
+        self.color_attachments[id.index()].name = Some(name.to_owned());
+        id
+    }
+
+    /// Sets the name of the attachment, for looking it up with `attachment_id`
+    /// or the `&str` lookups. With duplicate names the first attachment is found.
+    pub fn set_attachment_name(
+        &mut self,
+        attachment: impl AttachmentKey,
+        name: &str,
+    ) -> Result<(), FramebufferError> {
+        self.color_attachment_mut(attachment)?.name = Some(name.to_owned());
+        Ok(())
+    }
+
+    /// Returns the name of the attachment, None if it has no name or there is no such attachment
+    pub fn attachment_name(&self, attachment: impl AttachmentKey) -> Option<&str> {
+        let idx = attachment.attachment_index(self)?;
+        self.color_attachments[idx].name.as_deref()
+    }
+
+    /// Returns the handle of the attachment with the name
+    pub fn attachment_id(&self, name: &str) -> Option<AttachmentId> {
+        self.color_attachments
+            .iter()
+            .position(|a| a.name.as_deref() == Some(name))
+            .map(AttachmentId)
+    }
+
+    /// Sets if a texture attachment has a full mip chain, for sampling
+    /// it with trilinear filtering. Passes render to the first mip level, and the
+    /// rest are filled by `generate_mips`. The format needs a float sample type.
+    /// Invalidates resources, requires `configure`
+    pub fn set_attachment_mipmapped(
+        &mut self,
+        attachment: impl AttachmentKey,
+        mipmapped: bool,
+    ) -> Result<(), FramebufferError> {
+        let attachment = self.color_attachment_mut(attachment)?;
+        match &mut attachment.data {
+            ColorAttachmentData::Texture {
+                mipmapped: current, ..
+            } => *current = mipmapped,
+            ColorAttachmentData::Surface { .. } => return Err(FramebufferError::NoTexture),
+        }
+        attachment.configured = None;
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Returns the number of mip levels of the attachment
+    pub fn attachment_mip_level_count(&self, attachment: impl AttachmentKey) -> Option<u32> {
+        let idx = attachment.attachment_index(self)?;
+        Some(self.mip_level_count(idx))
+    }
+
+    fn mip_level_count(&self, idx: usize) -> u32 {
+        match &self.color_attachments[idx].data {
+            ColorAttachmentData::Texture {
+                mipmapped: true, ..
+            } => wgpu::Extent3d {
+                width: self.render_width(),
+                height: self.render_height(),
+                depth_or_array_layers: 1,
+            }
+            .max_mips(wgpu::TextureDimension::D2),
+            _ => 1,
+        }
+    }
+
+    /// Sets the adapter the device was created from. It is needed for knowing
+    /// the format features when `TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES` is enabled,
+    /// or the adapter is downlevel, such as on GL.
+    /// Without it only the features guaranteed for the device are relied on.
+    /// Invalidates resources, requires `configure`
+    pub fn set_adapter(&mut self, adapter: &wgpu::Adapter) {
+        self.adapter = Some(adapter.clone());
         self.dirty = true;
+        self.invalidate_resources();
     }
 
     /// Returns the number of color attachments bound to the `Framebuffer`
@@ -161,22 +794,163 @@ impl Framebuffer {
         self.dirty = true;
     }
 
-    /// Sets the clear color of all attachments
+    /// Returns the present mode the surfaces are configured with
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.present_mode
+    }
+
+    /// Returns the color format of the attachment
+    pub fn attachment_format(&self, attachment: impl AttachmentKey) -> Option<wgpu::TextureFormat> {
+        let idx = attachment.attachment_index(self)?;
+        Some(self.color_attachments[idx].color_format)
+    }
+
+    /// Returns the format render passes view the attachment in,
+    /// which pipelines need to target. It differs from `attachment_format`
+    /// only for surfaces with a `SurfaceOptions::view_format`.
+    pub fn attachment_view_format(
+        &self,
+        attachment: impl AttachmentKey,
+    ) -> Option<wgpu::TextureFormat> {
+        let idx = attachment.attachment_index(self)?;
+        Some(self.color_attachments[idx].view_format())
+    }
+
+    /// Sets the clear color of all attachments that are cleared on load.
+    /// Attachments set to `LoadOp::Load` are left as is.
     pub fn set_clear_color(&mut self, clear_color: &[f64; 4]) {
         for attachment in &mut self.color_attachments {
-            attachment.clear_color = *clear_color;
+            if let wgpu::LoadOp::Clear(color) = &mut attachment.load_op {
+                *color = to_color(clear_color);
+            }
         }
     }
 
+    /// Sets the clear color of the attachment, and makes it cleared on load
+    pub fn set_attachment_clear_color(
+        &mut self,
+        attachment: impl AttachmentKey,
+        clear_color: &[f64; 4],
+    ) -> Result<(), FramebufferError> {
+        self.color_attachment_mut(attachment)?.load_op = wgpu::LoadOp::Clear(to_color(clear_color));
+        Ok(())
+    }
+
+    /// Sets the load operation of the attachment.
+    /// `LoadOp::Load` keeps the previous contents, for example for accumulation.
+    /// Default is clearing to transparent black
+    pub fn set_attachment_load_op(
+        &mut self,
+        attachment: impl AttachmentKey,
+        load_op: wgpu::LoadOp<wgpu::Color>,
+    ) -> Result<(), FramebufferError> {
+        self.color_attachment_mut(attachment)?.load_op = load_op;
+        Ok(())
+    }
+
+    /// Sets if the attachment is stored after the pass. When multisampled,
+    /// it is the multisample texture, that needs storing for the next passes
+    /// to `LoadOp::Load` it.
+    /// By default it is stored, unless it is multisampled and only the
+    /// resolve target is kept.
+    pub fn set_attachment_store(
+        &mut self,
+        attachment: impl AttachmentKey,
+        store: bool,
+    ) -> Result<(), FramebufferError> {
+        self.color_attachment_mut(attachment)?.store = Some(if store {
+            wgpu::StoreOp::Store
+        } else {
+            wgpu::StoreOp::Discard
+        });
+        Ok(())
+    }
+
+    fn color_attachment_mut(
+        &mut self,
+        attachment: impl AttachmentKey,
+    ) -> Result<&mut ColorAttachment, FramebufferError> {
+        let idx = attachment
+            .attachment_index(self)
+            .ok_or(FramebufferError::UnknownAttachment)?;
+        Ok(&mut self.color_attachments[idx])
+    }
+
     /// Set the sample count, 1 for no multisampling.
+    /// It needs to be supported by the formats of all the attachments, see
+    /// `supported_sample_counts` and `set_sample_count_fallback`. Without
+    /// `set_adapter` it is checked against the guaranteed format features only.
     /// Invalidates resources, requires `aseemble`
     /// Default is 1
     pub fn set_sample_count(&mut self, sample_count: u32) {
         self.sample_count = sample_count;
+        self.requested_sample_count = sample_count;
+        self.dirty = true;
+        self.invalidate_resources();
+    }
+
+    /// Sets if `configure` falls back to the highest supported sample count
+    /// below the requested one, instead of failing with `UnsupportedSampleCount`.
+    /// Invalidates resources, requires `configure`
+    /// Default is false
+    pub fn set_sample_count_fallback(&mut self, fallback: bool) {
+        self.sample_count_fallback = fallback;
         self.dirty = true;
         self.invalidate_resources();
     }
 
+    /// Returns the sample counts that the formats of all the attachments and the
+    /// depth-stencil support on the device created from the adapter, in increasing
+    /// order. It always has 1. The adapter's format features are used when the device
+    /// does, that is with `TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES` or on downlevel
+    /// adapters, otherwise the guaranteed ones.
+    pub fn supported_sample_counts(
+        &self,
+        adapter: &wgpu::Adapter,
+        device: &wgpu::Device,
+    ) -> Vec<u32> {
+        self.sample_counts(Some(adapter), device)
+    }
+
+    /// The supported sample counts, with only the guaranteed format features
+    /// when the adapter isn't known
+    fn sample_counts(&self, adapter: Option<&wgpu::Adapter>, device: &wgpu::Device) -> Vec<u32> {
+        // Multisampled color attachments are resolved
+        let color_formats = self
+            .color_attachments
+            .iter()
+            .map(|attachment| (attachment.view_format(), true));
+        let depth_format = self.depth_stencil_format.map(|format| (format, false));
+        let mut counts = vec![1, 2, 4, 8, 16];
+        for (format, resolve) in color_formats.chain(depth_format) {
+            let features = format_features(adapter, device, format)
+                .unwrap_or_else(|| format.guaranteed_format_features(device.features()));
+            let resolvable = !resolve
+                || features
+                    .flags
+                    .contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE);
+            counts.retain(|&count| {
+                count == 1 || (resolvable && features.flags.sample_count_supported(count))
+            });
+        }
+        counts
+    }
+
+    /// Sets if the passes resolve the multisampled attachments to their output.
+    /// Turning it off lets several passes render to the same multisample textures,
+    /// such as opaque and then transparent passes with `LoadOp::Load`, and only
+    /// the last one resolve. Without resolving the multisample textures are
+    /// stored by default, and the surface frames are not rendered to.
+    /// Default is true, and it doesn't require `configure`
+    pub fn set_msaa_resolve(&mut self, resolve: bool) {
+        self.msaa_resolve = resolve;
+    }
+
+    /// Returns if the passes resolve the multisampled attachments
+    pub fn msaa_resolve(&self) -> bool {
+        self.msaa_resolve
+    }
+
     /// Sets the depth-stencil texture format, or None if no depth-stencil is needed
     /// Defaults to none
     /// Invalidates resource, requires `configure`
@@ -186,7 +960,90 @@ impl Framebuffer {
         self.invalidate_depth_stencil();
     }
 
-    /// Returns sample count, 1 meaning no multisampling
+    /// Sets usages for the depth-stencil texture in addition to `RENDER_ATTACHMENT`,
+    /// such as `TEXTURE_BINDING` for sampling it or `COPY_SRC` for reading it back.
+    /// Default is none
+    /// Invalidates resource, requires `configure`
+    pub fn set_depth_stencil_usage(&mut self, usage: wgpu::TextureUsages) {
+        self.depth_stencil_usage = usage;
+        self.dirty = true;
+        self.invalidate_depth_stencil();
+    }
+
+    /// Sets the number of array layers of the texture attachments and the depth-stencil
+    /// texture, each rendered with its own pass, see `begin_render_pass_layer`.
+    /// Surface attachments can only have one layer.
+    /// Default is 1
+    /// Invalidates resources, requires `configure`
+    pub fn set_layer_count(&mut self, layer_count: u32) {
+        self.layer_count = layer_count;
+        self.dirty = true;
+        self.invalidate_resources();
+    }
+
+    /// Sets if the layers are cubemap faces, so that the views of the whole
+    /// textures are `Cube` or `CubeArray`. Needs a layer count that is a
+    /// multiple of 6, and a square resolution.
+    /// Default is false
+    /// Invalidates resources, requires `configure`
+    pub fn set_cube(&mut self, cube: bool) {
+        self.cube = cube;
+        self.dirty = true;
+        self.invalidate_resources();
+    }
+
+    /// Sets the number of passes per frame that get begin and end GPU timestamps,
+    /// 0 for none. The device needs `Features::TIMESTAMP_QUERY`, without it
+    /// no timestamps are written.
+    /// Resolve them with `resolve_timestamps`, and get the durations with
+    /// `read_timestamps` and `pass_durations_ms`.
+    /// Default is 0
+    /// Invalidates resources, requires `configure`
+    pub fn set_timestamp_pass_count(&mut self, pass_count: u32) {
+        self.timestamp_pass_count = pass_count;
+        self.timestamps = None;
+        self.dirty = true;
+    }
+
+    /// Sets the number of occlusion queries in the query set the passes are
+    /// given, 0 for none. Queries are written with `RenderPass::begin_occlusion_query`,
+    /// and their sample counts read with `read_occlusion_queries`.
+    /// Default is 0
+    /// Invalidates resources, requires `configure`
+    pub fn set_occlusion_query_count(&mut self, query_count: u32) {
+        self.occlusion_query_count = query_count.min(wgpu::QUERY_SET_MAX_QUERIES);
+        self.occlusion_query_set = None;
+        self.dirty = true;
+    }
+
+    /// Returns the number of occlusion queries
+    pub fn occlusion_query_count(&self) -> u32 {
+        self.occlusion_query_count
+    }
+
+    /// Returns the occlusion query set the passes are given, for resolving it manually
+    pub fn occlusion_query_set(&self) -> Option<&wgpu::QuerySet> {
+        self.occlusion_query_set.as_ref()
+    }
+
+    /// Returns the number of layers
+    pub fn layer_count(&self) -> u32 {
+        self.layer_count
+    }
+
+    /// Returns if the layers are cubemap faces
+    pub fn is_cube(&self) -> bool {
+        self.cube
+    }
+
+    /// Returns the format of the depth-stencil attachment
+    pub fn depth_stencil_format(&self) -> Option<wgpu::TextureFormat> {
+        self.depth_stencil_format
+    }
+
+    /// Returns sample count, 1 meaning no multisampling.
+    /// After `configure` it is the one in use, that can be lower than the
+    /// requested one with `set_sample_count_fallback`.
     pub fn sample_count(&self) -> u32 {
         self.sample_count
     }
@@ -209,6 +1066,54 @@ impl Framebuffer {
         self.invalidate_resources();
     }
 
+    /// Sets the scale of the resolution the passes render at, such as 0.5 for
+    /// rendering at half the resolution. The texture and depth-stencil attachments
+    /// are created at the scaled size, and the surface attachments get textures
+    /// of it, that `upscale` draws to the surfaces.
+    /// The surfaces are not reconfigured, so it can be changed every frame,
+    /// and setting the current scale again does nothing.
+    /// Panics if the scale is not positive and finite.
+    /// Default is 1.0
+    /// Invalidates resources, requires `configure`
+    pub fn set_render_scale(&mut self, render_scale: f32) {
+        assert!(
+            render_scale > 0.0 && render_scale.is_finite(),
+            "Invalid render scale {render_scale}"
+        );
+        if render_scale == self.render_scale {
+            return;
+        }
+        self.render_scale = render_scale;
+        self.dirty = true;
+        self.invalidate_resources();
+    }
+
+    /// Returns the render scale
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    /// Sets the filtering `upscale` uses.
+    /// Default is `Linear`
+    pub fn set_upscale_filter(&mut self, filter: wgpu::FilterMode) {
+        self.upscale_filter = filter;
+    }
+
+    /// Returns width the passes render at, the width scaled by the render scale
+    pub fn render_width(&self) -> u32 {
+        scale_dimension(self.resolution.0, self.render_scale)
+    }
+
+    /// Returns height the passes render at, the height scaled by the render scale
+    pub fn render_height(&self) -> u32 {
+        scale_dimension(self.resolution.1, self.render_scale)
+    }
+
+    /// Returns true if the surfaces are rendered through scaled textures
+    fn is_scaled(&self) -> bool {
+        (self.render_width(), self.render_height()) != self.resolution
+    }
+
     pub fn set_depth_store(&mut self, store: bool) {
         self.depth_store = if store {
             wgpu::StoreOp::Store
@@ -220,104 +1125,424 @@ impl Framebuffer {
         self.depth_load_op = load_op;
     }
 
-    pub fn attachment_view(&self, idx: usize) -> Option<&wgpu::TextureView> {
+    /// Sets if the stencil aspect is stored after the pass.
+    /// Default is to discard it.
+    pub fn set_stencil_store(&mut self, store: bool) {
+        self.stencil_store = if store {
+            wgpu::StoreOp::Store
+        } else {
+            wgpu::StoreOp::Discard
+        };
+    }
+
+    /// Sets the load operation of the stencil aspect, `LoadOp::Clear` holding the clear value.
+    /// Default is clearing to 0.
+    pub fn set_stencil_load_op(&mut self, load_op: wgpu::LoadOp<u32>) {
+        self.stencil_load_op = load_op;
+    }
+
+    /// Returns the view of all the layers of the attachment, for sampling it.
+    /// It is `D2Array`, `Cube` or `CubeArray` when layered.
+    /// The attachment is found by index, `AttachmentId` or name.
+    pub fn attachment_view(&self, attachment: impl AttachmentKey) -> Option<&wgpu::TextureView> {
+        let idx = attachment.attachment_index(self)?;
         self.color_attachments[idx]
             .configured
             .as_ref()
-            .and_then(|a| a.resolve_view.as_ref().or(a.attachment_view.as_ref()))
+            .and_then(|a| a.output_view.as_ref().or(a.msaa_view.as_ref()))
     }
 
-    pub fn attachment_texture(&self, idx: usize) -> Option<&wgpu::Texture> {
+    /// Returns the view of the texture the passes of this frame render to, the same
+    /// as `attachment_view`
+    pub fn current_view(&self, attachment: impl AttachmentKey) -> Option<&wgpu::TextureView> {
+        self.attachment_view(attachment)
+    }
+
+    /// Returns the view of the previous frame's texture of a history attachment,
+    /// see `add_history_attachment`
+    pub fn previous_view(&self, attachment: impl AttachmentKey) -> Option<&wgpu::TextureView> {
+        let idx = attachment.attachment_index(self)?;
+        self.color_attachments[idx]
+            .configured
+            .as_ref()?
+            .previous_output_view
+            .as_ref()
+    }
+
+    /// Returns the previous frame's texture of a history attachment
+    pub fn previous_texture(&self, attachment: impl AttachmentKey) -> Option<&wgpu::Texture> {
+        let idx = attachment.attachment_index(self)?;
         match &self.color_attachments[idx].data {
+            ColorAttachmentData::Texture {
+                previous_texture, ..
+            } => previous_texture.as_ref(),
             ColorAttachmentData::Surface { .. } => None,
-            ColorAttachmentData::Texture { color_texture } => color_texture.as_ref(),
         }
     }
 
+    /// Returns the single layer view of the attachment, None for surfaces
+    pub fn attachment_layer_view(
+        &self,
+        attachment: impl AttachmentKey,
+        layer: u32,
+    ) -> Option<&wgpu::TextureView> {
+        let idx = attachment.attachment_index(self)?;
+        self.color_attachments[idx]
+            .configured
+            .as_ref()?
+            .layer_views
+            .get(layer as usize)
+    }
+
+    /// Returns the multisample texture the passes render to, None when not multisampled.
+    /// It only has the `RENDER_ATTACHMENT` usage, and one layer that is used
+    /// for the passes to every layer.
+    pub fn msaa_texture(&self, attachment: impl AttachmentKey) -> Option<&wgpu::Texture> {
+        let idx = attachment.attachment_index(self)?;
+        self.color_attachments[idx]
+            .configured
+            .as_ref()?
+            .msaa_texture
+            .as_ref()
+    }
+
+    /// Returns the view of the multisample texture, None when not multisampled
+    pub fn msaa_view(&self, attachment: impl AttachmentKey) -> Option<&wgpu::TextureView> {
+        let idx = attachment.attachment_index(self)?;
+        self.color_attachments[idx]
+            .configured
+            .as_ref()?
+            .msaa_view
+            .as_ref()
+    }
+
+    /// Returns the texture of the attachment, None for surfaces
+    pub fn attachment_texture(&self, attachment: impl AttachmentKey) -> Option<&wgpu::Texture> {
+        let idx = attachment.attachment_index(self)?;
+        match &self.color_attachments[idx].data {
+            ColorAttachmentData::Surface { .. } => None,
+            ColorAttachmentData::Texture { color_texture, .. } => color_texture.as_ref(),
+        }
+    }
+
+    /// Returns the depth-stencil texture, if configured
+    pub fn depth_stencil_texture(&self) -> Option<&wgpu::Texture> {
+        self.depth_stencil.as_ref().map(|d| &d.texture)
+    }
+
+    /// Returns the view of all the aspects of the depth-stencil texture, if configured
+    pub fn depth_stencil_view(&self) -> Option<&wgpu::TextureView> {
+        self.depth_stencil.as_ref().map(|d| &d.view)
+    }
+
+    /// Returns the depth-only view of the depth-stencil texture, for sampling depth.
+    /// None if not configured or the format has no depth aspect
+    pub fn depth_view(&self) -> Option<&wgpu::TextureView> {
+        self.depth_stencil.as_ref()?.depth_view.as_ref()
+    }
+
+    /// Returns the stencil-only view of the depth-stencil texture.
+    /// None if not configured or the format has no stencil aspect
+    pub fn stencil_view(&self) -> Option<&wgpu::TextureView> {
+        self.depth_stencil.as_ref()?.stencil_view.as_ref()
+    }
+
+    /// Reads back the first layer of the texture of the attachment, which is the resolve
+    /// target when multisampled. The attachment needs `COPY_SRC` usage,
+    /// see `add_texture_attachment_with_usage`.
+    ///
+    /// The copy is submitted to the queue right away, so call it after the
+    /// passes rendering to the attachment are submitted. The returned future
+    /// doesn't borrow the framebuffer, so it can be given to `block_on`.
+    pub fn read_attachment<K: AttachmentKey>(
+        &self,
+        attachment: K,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> impl Future<Output = Result<Image, FramebufferError>> + use<K> {
+        self.read_attachment_layer(attachment, 0, device, queue)
+    }
+
+    /// Reads back a layer of the texture of the attachment, as `read_attachment`
+    pub fn read_attachment_layer<K: AttachmentKey>(
+        &self,
+        attachment: K,
+        layer: u32,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> impl Future<Output = Result<Image, FramebufferError>> + use<K> {
+        let staging = attachment
+            .attachment_index(self)
+            .ok_or(FramebufferError::UnknownAttachment)
+            .and_then(|idx| {
+                self.attachment_texture(idx)
+                    .ok_or(FramebufferError::NoTexture)
+            })
+            .and_then(|texture| Staging::new(texture, layer, device, queue));
+        let device = device.clone();
+        async move { staging?.read(&device).await }
+    }
+
+    /// Reads back the sample counts of the occlusion queries, the number of
+    /// samples that passed the depth and stencil tests between the begin and
+    /// end of each query. See `set_occlusion_query_count`.
+    ///
+    /// The queries are resolved in a submission of their own right away, so call
+    /// it after the passes writing them are submitted. The returned future doesn't
+    /// borrow anything, so it can be given to `block_on`.
+    pub fn read_occlusion_queries(
+        &self,
+        queries: std::ops::Range<u32>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> impl Future<Output = Result<Vec<u64>, FramebufferError>> + use<> {
+        let staging = match &self.occlusion_query_set {
+            _ if queries.is_empty() => None,
+            _ if queries.end > self.occlusion_query_count => {
+                Some(Err(FramebufferError::QueryOutOfRange(queries.end - 1)))
+            }
+            Some(query_set) => Some(Ok(QueryStaging::new(query_set, queries, device, queue))),
+            None => Some(Err(FramebufferError::NotConfigured)),
+        };
+        let device = device.clone();
+        async move {
+            match staging {
+                Some(staging) => staging?.read(&device).await,
+                None => Ok(Vec::new()),
+            }
+        }
+    }
+
+    /// Reads back the attachment like `read_attachment`, and writes it
+    /// to a file, in the format chosen by the extension: `png`, `ppm` or `hdr`.
+    /// See `Image::encode` for the supported texture formats.
+    pub fn save_attachment<K: AttachmentKey>(
+        &self,
+        attachment: K,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        path: &std::path::Path,
+    ) -> impl Future<Output = Result<(), FramebufferError>> + use<K> {
+        let image = self.read_attachment(attachment, device, queue);
+        let path = path.to_path_buf();
+        async move { image.await?.save(path) }
+    }
+
     /// Returns if resources had been invalidated, and needs `configure`
     pub fn needs_configure(&self) -> bool {
         self.dirty
     }
 
+    /// Checks that every attachment has the views a render pass needs
+    fn is_configured(&self) -> bool {
+        !self.dirty
+            && self
+                .color_attachments
+                .iter()
+                .all(|a| match (&a.data, &a.configured) {
+                    (_, None) => false,
+                    (ColorAttachmentData::Surface { .. }, Some(c)) => {
+                        c.layer_views.len() == self.is_scaled() as usize
+                    }
+                    (ColorAttachmentData::Texture { .. }, Some(c)) => {
+                        c.layer_views.len() == self.layer_count as usize
+                    }
+                })
+    }
+
     /// (re)creates all the resources with the current configuration
+    /// Panics if the configuration is invalid, see `try_configure`
     pub fn configure(&mut self, device: &wgpu::Device) {
+        if let Err(err) = self.try_configure(device) {
+            panic!("Framebuffer configure failed: {err}");
+        }
+    }
+
+    /// (re)creates all the resources with the current configuration
+    /// On error the framebuffer stays unconfigured
+    pub fn try_configure(&mut self, device: &wgpu::Device) -> Result<(), FramebufferError> {
         debug_assert!(
             !self.needs_present(),
             "Live swapchain frames that were not presented while reconfiguring!"
         );
         if !self.dirty {
             // Nothing changed
-            return;
+            return Ok(());
+        }
+        self.sample_count = self.requested_sample_count;
+        if self.sample_count_fallback {
+            let requested = self.requested_sample_count;
+            self.sample_count = self
+                .sample_counts(self.adapter.as_ref(), device)
+                .into_iter()
+                .rfind(|&count| count <= requested)
+                .unwrap_or(1);
         }
+        self.validate(device)?;
         self.dirty = false;
+        self.prepared_passes.clear();
+        if self.device.as_ref() != Some(device) {
+            // The pipelines belong to the old device
+            self.mip_generator = None;
+            self.blitter = None;
+            self.timestamps = None;
+            self.occlusion_query_set = None;
+            // The surfaces are configured again for the new device
+            for attachment in &mut self.color_attachments {
+                if let ColorAttachmentData::Surface { config, .. } = &mut attachment.data {
+                    *config = None;
+                }
+            }
+        }
+        self.device = Some(device.clone());
+        if self.timestamps.is_none()
+            && self.timestamp_pass_count > 0
+            && device.features().contains(wgpu::Features::TIMESTAMP_QUERY)
+        {
+            self.timestamps = Some(TimestampQueries::new(device, self.timestamp_pass_count));
+        }
+        if self.occlusion_query_set.is_none() && self.occlusion_query_count > 0 {
+            self.occlusion_query_set = Some(device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Framebuffer occlusion queries"),
+                ty: wgpu::QueryType::Occlusion,
+                count: self.occlusion_query_count,
+            }));
+        }
 
-        for attachment in &mut self.color_attachments {
+        let view_dimension = self.view_dimension();
+        let render_size = wgpu::Extent3d {
+            width: self.render_width(),
+            height: self.render_height(),
+            depth_or_array_layers: 1,
+        };
+        let scaled = self.is_scaled();
+        for idx in 0..self.color_attachments.len() {
+            let mip_level_count = self.mip_level_count(idx);
+            let attachment = &mut self.color_attachments[idx];
             let mut output_view = None;
+            let mut layer_views = Vec::new();
+            let mut previous_output_view = None;
+            let mut previous_layer_views = Vec::new();
+            let view_format = attachment.view_format();
             match attachment.data {
                 ColorAttachmentData::Surface {
                     ref mut surface,
                     ref mut frame,
+                    config: ref mut surface_config,
+                    ref options,
                 } => {
                     let config = wgpu::SurfaceConfiguration {
-                        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | options.usage,
                         format: attachment.color_format,
                         width: self.resolution.0,
                         height: self.resolution.1,
                         present_mode: self.present_mode,
-                        alpha_mode: wgpu::CompositeAlphaMode::Auto,
-                        view_formats: vec![],
-                        desired_maximum_frame_latency: 2,
+                        alpha_mode: options.alpha_mode,
+                        view_formats: options.view_format.into_iter().collect(),
+                        desired_maximum_frame_latency: options.desired_maximum_frame_latency,
                     };
-                    *frame = None;
-                    surface.configure(device, &config);
+                    // Render scale changes don't need the surface reconfigured
+                    if surface_config.as_ref() != Some(&config) {
+                        *frame = None;
+                        surface.configure(device, &config);
+                        *surface_config = Some(config);
+                    }
+                    if scaled {
+                        let texture = device.create_texture(&wgpu::TextureDescriptor {
+                            label: Some("Framebuffer scaled surface texture"),
+                            size: render_size,
+                            mip_level_count: 1,
+                            sample_count: 1,
+                            dimension: wgpu::TextureDimension::D2,
+                            format: view_format,
+                            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                                | wgpu::TextureUsages::TEXTURE_BINDING,
+                            view_formats: &[],
+                        });
+                        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                        output_view = Some(view.clone());
+                        layer_views = vec![view];
+                    }
                 }
                 ColorAttachmentData::Texture {
                     ref mut color_texture,
+                    ref mut previous_texture,
+                    usage,
+                    history,
+                    ..
                 } => {
-                    let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    // Storage is only added when it is known to be supported
+                    let storage_supported =
+                        format_features(self.adapter.as_ref(), device, attachment.color_format)
+                            .is_some_and(|f| {
+                                f.allowed_usages
+                                    .contains(wgpu::TextureUsages::STORAGE_BINDING)
+                            });
+                    let storage_usage = if storage_supported {
+                        wgpu::TextureUsages::STORAGE_BINDING
+                    } else {
+                        wgpu::TextureUsages::empty()
+                    };
+                    let texture_descriptor = wgpu::TextureDescriptor {
                         label: Some("Framebuffer Texture"),
                         size: wgpu::Extent3d {
-                            width: self.resolution.0,
-                            height: self.resolution.1,
-                            depth_or_array_layers: 1,
+                            depth_or_array_layers: self.layer_count,
+                            ..render_size
                         },
-                        mip_level_count: 1,
+                        mip_level_count,
                         sample_count: 1,
                         dimension: wgpu::TextureDimension::D2,
                         format: attachment.color_format,
                         usage: wgpu::TextureUsages::RENDER_ATTACHMENT
                             | wgpu::TextureUsages::TEXTURE_BINDING
-                            | wgpu::TextureUsages::STORAGE_BINDING,
+                            | storage_usage
+                            | usage,
                         view_formats: &[],
-                    });
-                    let tex_view = texture.create_view(&wgpu::TextureViewDescriptor {
-                        label: Some("Framebuffer Texture view"),
-                        format: Some(attachment.color_format),
-                        dimension: Some(wgpu::TextureViewDimension::D2),
-                        aspect: wgpu::TextureAspect::All,
-                        base_mip_level: 0,
-                        mip_level_count: None,
-                        base_array_layer: 0,
-                        array_layer_count: None,
-                        usage: None,
-                    });
-                    output_view = Some(tex_view);
+                    };
+                    let full_view = |texture: &wgpu::Texture| {
+                        texture.create_view(&wgpu::TextureViewDescriptor {
+                            label: Some("Framebuffer Texture view"),
+                            format: Some(attachment.color_format),
+                            dimension: Some(view_dimension),
+                            aspect: wgpu::TextureAspect::All,
+                            base_mip_level: 0,
+                            mip_level_count: None,
+                            base_array_layer: 0,
+                            array_layer_count: None,
+                            usage: None,
+                        })
+                    };
+                    let layer_views_of = |texture: &wgpu::Texture| {
+                        (0..self.layer_count)
+                            .map(|layer| texture.create_view(&layer_view_descriptor(layer)))
+                            .collect()
+                    };
+
+                    let texture = device.create_texture(&texture_descriptor);
+                    output_view = Some(full_view(&texture));
+                    layer_views = layer_views_of(&texture);
                     *color_texture = Some(texture);
+
+                    *previous_texture = history.then(|| {
+                        let texture = device.create_texture(&wgpu::TextureDescriptor {
+                            label: Some("Framebuffer history Texture"),
+                            ..texture_descriptor
+                        });
+                        previous_output_view = Some(full_view(&texture));
+                        previous_layer_views = layer_views_of(&texture);
+                        texture
+                    });
                 }
             }
 
             if self.sample_count > 1 {
                 let msaa_texture = device.create_texture(&wgpu::TextureDescriptor {
                     label: Some("Framebuffer MSAA Texture"),
-                    size: wgpu::Extent3d {
-                        width: self.resolution.0,
-                        height: self.resolution.1,
-                        depth_or_array_layers: 1,
-                    },
+                    size: render_size,
                     mip_level_count: 1,
                     sample_count: self.sample_count,
                     dimension: wgpu::TextureDimension::D2,
-                    format: attachment.color_format,
+                    format: view_format,
                     usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
                     view_formats: &[],
                 });
@@ -325,38 +1550,290 @@ impl Framebuffer {
                     Some(msaa_texture.create_view(&wgpu::TextureViewDescriptor::default()));
 
                 attachment.configured = Some(ColorAttachmentConfigured {
-                    // multisample_texture: Some(msaa_texture),
-                    attachment_view: msaa_view,
-                    resolve_view: output_view,
+                    msaa_texture: Some(msaa_texture),
+                    msaa_view,
+                    output_view,
+                    layer_views,
+                    previous_output_view,
+                    previous_layer_views,
                 });
             } else {
                 attachment.configured = Some(ColorAttachmentConfigured {
-                    // multisample_texture: None,
-                    attachment_view: output_view,
-                    resolve_view: None,
+                    msaa_texture: None,
+                    msaa_view: None,
+                    output_view,
+                    layer_views,
+                    previous_output_view,
+                    previous_layer_views,
                 });
             }
         }
 
         if let Some(depth_format) = self.depth_stencil_format {
-            self.depth_stencil_view = Some(
-                device
-                    .create_texture(&wgpu::TextureDescriptor {
-                        size: wgpu::Extent3d {
-                            width: self.width(),
-                            height: self.height(),
-                            depth_or_array_layers: 1,
-                        },
-                        mip_level_count: 1,
-                        sample_count: self.sample_count,
-                        dimension: wgpu::TextureDimension::D2,
-                        format: depth_format,
-                        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-                        label: Some("wgpu-util depth texture"),
-                        view_formats: &[],
-                    })
-                    .create_view(&wgpu::TextureViewDescriptor::default()),
+            // Multisampled textures can't be layered, so one layer is used for every pass
+            let (depth_layers, dimension) = if self.sample_count > 1 {
+                (1, wgpu::TextureViewDimension::D2)
+            } else {
+                (self.layer_count, view_dimension)
+            };
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                size: wgpu::Extent3d {
+                    depth_or_array_layers: depth_layers,
+                    ..render_size
+                },
+                mip_level_count: 1,
+                sample_count: self.sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: depth_format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | self.depth_stencil_usage,
+                label: Some("wgpu-util depth texture"),
+                view_formats: &[],
+            });
+            let aspect_view = |aspect| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("wgpu-util depth texture aspect view"),
+                    format: depth_format.aspect_specific_format(aspect),
+                    dimension: Some(dimension),
+                    aspect,
+                    ..Default::default()
+                })
+            };
+            let depth_view = depth_format
+                .has_depth_aspect()
+                .then(|| aspect_view(wgpu::TextureAspect::DepthOnly));
+            let stencil_view = depth_format
+                .has_stencil_aspect()
+                .then(|| aspect_view(wgpu::TextureAspect::StencilOnly));
+            self.depth_stencil = Some(DepthStencilConfigured {
+                view: texture.create_view(&wgpu::TextureViewDescriptor {
+                    dimension: Some(dimension),
+                    ..Default::default()
+                }),
+                depth_view,
+                stencil_view,
+                layer_views: (0..depth_layers)
+                    .map(|layer| texture.create_view(&layer_view_descriptor(layer)))
+                    .collect(),
+                texture,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks if the format supports the usage.
+    /// When the format features can't be known, the usage is trusted.
+    fn supports_usage(
+        &self,
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        usage: wgpu::TextureUsages,
+    ) -> bool {
+        format_features(self.adapter.as_ref(), device, format)
+            .is_none_or(|f| f.allowed_usages.contains(usage))
+    }
+
+    /// Dimension of the views of all the layers
+    fn view_dimension(&self) -> wgpu::TextureViewDimension {
+        match (self.cube, self.layer_count) {
+            (true, 6) => wgpu::TextureViewDimension::Cube,
+            (true, _) => wgpu::TextureViewDimension::CubeArray,
+            (false, 1) => wgpu::TextureViewDimension::D2,
+            (false, _) => wgpu::TextureViewDimension::D2Array,
+        }
+    }
+
+    /// Checks that the current configuration can be created on the device
+    fn validate(&self, device: &wgpu::Device) -> Result<(), FramebufferError> {
+        if self.resolution.0 == 0 || self.resolution.1 == 0 {
+            return Err(FramebufferError::ZeroSize);
+        }
+        let max_dimension = device.limits().max_texture_dimension_2d;
+        if [
+            self.resolution.0,
+            self.resolution.1,
+            self.render_width(),
+            self.render_height(),
+        ]
+        .into_iter()
+        .any(|dimension| dimension > max_dimension)
+        {
+            return Err(FramebufferError::TooLarge);
+        }
+        let max_attachments =
+            (device.limits().max_color_attachments as usize).min(MAX_COLOR_ATTACHMENTS);
+        if self.color_attachments.len() > max_attachments {
+            return Err(FramebufferError::TooManyAttachments);
+        }
+        let has_surface = self
+            .color_attachments
+            .iter()
+            .any(|a| matches!(a.data, ColorAttachmentData::Surface { .. }));
+        if self.layer_count == 0
+            || self.layer_count > device.limits().max_texture_array_layers
+            || (self.layer_count > 1 && has_surface)
+            || (self.cube
+                && (!self.layer_count.is_multiple_of(6)
+                    || self.render_width() != self.render_height()))
+        {
+            return Err(FramebufferError::InvalidLayers);
+        }
+        for attachment in &self.color_attachments {
+            let usage = match &attachment.data {
+                ColorAttachmentData::Surface { options, .. } => {
+                    if options.view_format.is_some_and(|view_format| {
+                        view_format.remove_srgb_suffix()
+                            != attachment.color_format.remove_srgb_suffix()
+                    }) {
+                        return Err(FramebufferError::UnsupportedFormat(
+                            attachment.view_format(),
+                        ));
+                    }
+                    wgpu::TextureUsages::RENDER_ATTACHMENT | options.usage
+                }
+                ColorAttachmentData::Texture { usage, .. } => {
+                    wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING
+                        | *usage
+                }
+            };
+            // Mips are generated with float texture loads
+            let mipmapped = matches!(
+                attachment.data,
+                ColorAttachmentData::Texture {
+                    mipmapped: true,
+                    ..
+                }
+            );
+            let float = matches!(
+                attachment.color_format.sample_type(None, None),
+                Some(wgpu::TextureSampleType::Float { .. })
             );
+            if attachment.color_format.is_depth_stencil_format()
+                || (mipmapped && !float)
+                || !self.supports_usage(device, attachment.color_format, usage)
+            {
+                return Err(FramebufferError::UnsupportedFormat(attachment.color_format));
+            }
+            // Surfaces can only be checked when the adapter is known
+            if let (
+                ColorAttachmentData::Surface {
+                    surface, options, ..
+                },
+                Some(adapter),
+            ) = (&attachment.data, &self.adapter)
+            {
+                let caps = surface.get_capabilities(adapter);
+                if caps.formats.is_empty() {
+                    return Err(FramebufferError::SurfaceUnsupported);
+                }
+                if !caps.formats.contains(&attachment.color_format) {
+                    return Err(FramebufferError::UnsupportedFormat(attachment.color_format));
+                }
+                // The automatic modes are resolved by wgpu to supported ones
+                let automatic = matches!(
+                    self.present_mode,
+                    wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync
+                );
+                if !automatic && !caps.present_modes.contains(&self.present_mode) {
+                    return Err(FramebufferError::UnsupportedPresentMode(self.present_mode));
+                }
+                if !caps.usages.contains(usage) {
+                    return Err(FramebufferError::MissingUsage(usage - caps.usages));
+                }
+                if options.alpha_mode != wgpu::CompositeAlphaMode::Auto
+                    && !caps.alpha_modes.contains(&options.alpha_mode)
+                {
+                    return Err(FramebufferError::UnsupportedAlphaMode(options.alpha_mode));
+                }
+            }
+        }
+        if !self
+            .sample_counts(self.adapter.as_ref(), device)
+            .contains(&self.sample_count)
+        {
+            return Err(FramebufferError::UnsupportedSampleCount(self.sample_count));
+        }
+        if let Some(depth_format) = self.depth_stencil_format
+            && (!depth_format.is_depth_stencil_format()
+                || !self.supports_usage(
+                    device,
+                    depth_format,
+                    wgpu::TextureUsages::RENDER_ATTACHMENT | self.depth_stencil_usage,
+                ))
+        {
+            return Err(FramebufferError::UnsupportedFormat(depth_format));
+        }
+        Ok(())
+    }
+
+    /// Records passes that fill the mip chains of the mipmapped attachments
+    /// from their first mip levels, see `set_attachment_mipmapped`.
+    /// Call it after the passes rendering to the attachments are recorded.
+    /// Panics if the framebuffer is not configured, see `try_generate_mips`
+    pub fn generate_mips(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if let Err(err) = self.try_generate_mips(encoder) {
+            panic!("Framebuffer mip generation failed: {err}");
+        }
+    }
+
+    /// Records passes that fill the mip chains of the mipmapped attachments,
+    /// as `generate_mips`
+    pub fn try_generate_mips(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<(), FramebufferError> {
+        if !self.is_configured() {
+            return Err(FramebufferError::NotConfigured);
+        }
+        let Some(device) = &self.device else {
+            return Err(FramebufferError::NotConfigured);
+        };
+        for attachment in &self.color_attachments {
+            if let ColorAttachmentData::Texture {
+                color_texture: Some(texture),
+                mipmapped: true,
+                ..
+            } = &attachment.data
+            {
+                self.mip_generator
+                    .get_or_insert_with(|| MipGenerator::new(device))
+                    .generate(encoder, texture);
+            }
+        }
+        Ok(())
+    }
+
+    /// Records passes that draw the textures rendered at the render scale to
+    /// the live surface frames, see `set_render_scale`.
+    /// Call it after the render pass is ended, before submitting and `present`.
+    /// Nothing is drawn when not scaled or there are no live frames.
+    pub fn upscale(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(device) = &self.device else {
+            return;
+        };
+        let mut live_frames = self.live_frame.iter();
+        for attachment in &self.color_attachments {
+            if !matches!(attachment.data, ColorAttachmentData::Surface { .. }) {
+                continue;
+            }
+            let Some(live_frame) = live_frames.next() else {
+                return;
+            };
+            if let Some(scaled_view) = attachment
+                .configured
+                .as_ref()
+                .and_then(|c| c.output_view.as_ref())
+            {
+                self.blitter
+                    .get_or_insert_with(|| Blitter::new(device))
+                    .blit_whole(
+                        encoder,
+                        scaled_view,
+                        &live_frame.view,
+                        attachment.view_format(),
+                        self.upscale_filter,
+                    );
+            }
         }
     }
 
@@ -378,145 +1855,351 @@ impl Framebuffer {
 
     /// Begins a render pass
     /// Remember to `present` after pass is submitted.
+    /// Panics if the pass can't be started, see `try_begin_render_pass`
     pub fn begin_render_pass<'a>(
         &'a mut self,
         encoder: &'a mut wgpu::CommandEncoder,
     ) -> wgpu::RenderPass<'a> {
+        self.begin_render_pass_layer(encoder, 0)
+    }
+
+    /// Begins a render pass to one layer of the attachments,
+    /// such as a cubemap face or a shadow cascade.
+    /// Panics if the pass can't be started, see `try_begin_render_pass_layer`
+    pub fn begin_render_pass_layer<'a>(
+        &'a mut self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        layer: u32,
+    ) -> wgpu::RenderPass<'a> {
+        match self.try_begin_render_pass_layer(encoder, layer) {
+            Ok(pass) => pass,
+            Err(err) => panic!("Framebuffer render pass failed: {err}"),
+        }
+    }
+
+    /// Begins a render pass
+    /// Remember to `present` after pass is submitted.
+    /// The passes before `present` render to the same surface frames.
+    /// Outdated or lost surfaces are reconfigured and acquired again.
+    /// On error no swapchain frames are left live, and if
+    /// `FramebufferError::should_skip_frame` is true the frame can just be skipped.
+    pub fn try_begin_render_pass<'a>(
+        &'a mut self,
+        encoder: &'a mut wgpu::CommandEncoder,
+    ) -> Result<wgpu::RenderPass<'a>, FramebufferError> {
+        self.try_begin_render_pass_layer(encoder, 0)
+    }
+
+    /// Begins a render pass to one layer of the attachments, as `try_begin_render_pass`
+    pub fn try_begin_render_pass_layer<'a>(
+        &'a mut self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        layer: u32,
+    ) -> Result<wgpu::RenderPass<'a>, FramebufferError> {
         // The lifetimes above are telling that the RenderPass must not be
         // dropped before self or encoder, as the RenderPass will refer to
         // values in them
 
-        debug_assert!(self.live_frame.is_empty());
-        assert!(!self.dirty, "Framebuffer was modified but not reconfigured");
-
-        let mut color_attachments = Vec::new();
+        if !self.is_configured() {
+            return Err(FramebufferError::NotConfigured);
+        }
+        if layer >= self.layer_count {
+            return Err(FramebufferError::LayerOutOfRange(layer));
+        }
 
         // Start acquire the swapchain frames in separate loop,
         // so that we can mutate self to store them, when the
         // renderpass borrows it
-        for attachment in &self.color_attachments {
-            if let ColorAttachmentData::Surface { surface, .. } = &attachment.data {
-                let new_frame = surface
-                    .get_current_texture()
-                    .expect("Timeout when acquiring next swap chain texture");
-                let frame_view = new_frame.texture.create_view(&Default::default());
-
-                self.live_frame.push(LiveFrame {
-                    frame: new_frame,
-                    view: frame_view,
-                });
-            }
+        if self.live_frame.is_empty() {
+            self.acquire_live_frames()?;
         }
 
-        let mut swapchain_idx = 0;
-        // TODO: retain the vec, update only when dirty or surface attachment
-        for attachment in &self.color_attachments {
-            let attachment_view;
-            let resolve_view;
-            match &attachment.data {
-                ColorAttachmentData::Surface { .. } => {
-                    let frame_view = &self.live_frame.get(swapchain_idx).unwrap().view;
-                    let configured = attachment
-                        .configured
-                        .as_ref()
-                        .expect("Unconfigured attachment, did you call configure()?");
-
-                    if configured.attachment_view.is_some() {
-                        attachment_view = configured.attachment_view.as_ref().unwrap();
-                        resolve_view = Some(frame_view);
-                    } else {
-                        attachment_view = frame_view;
-                        resolve_view = None;
-                    }
+        let key = (layer, self.msaa_resolve);
+        if !self.prepared_passes.contains_key(&key) {
+            let prepared = self.prepare_pass(layer);
+            self.prepared_passes.insert(key, prepared);
+        }
+        let timestamp_pass = self.timestamps.as_mut().and_then(|t| t.next_pass());
+        let Some(prepared) = self.prepared_passes.get(&key) else {
+            unreachable!()
+        };
 
-                    swapchain_idx += 1;
-                }
-                ColorAttachmentData::Texture { color_texture: _ } => {
-                    attachment_view = attachment
-                        .configured
-                        .as_ref()
-                        .unwrap()
-                        .attachment_view
-                        .as_ref()
-                        .unwrap();
-                    resolve_view = attachment
-                        .configured
-                        .as_ref()
-                        .unwrap()
-                        .resolve_view
-                        .as_ref();
-                }
-            }
+        // Only the live surface frames change between the passes, the rest is retained
+        let mut color_attachments: [Option<wgpu::RenderPassColorAttachment>;
+            MAX_COLOR_ATTACHMENTS] = Default::default();
+        let mut live_frames = self.live_frame.iter();
+        for ((slot, attachment), prepared) in color_attachments
+            .iter_mut()
+            .zip(&self.color_attachments)
+            .zip(&prepared.color_attachments)
+        {
+            let frame_view = if prepared.surface {
+                let Some(live_frame) = live_frames.next() else {
+                    unreachable!()
+                };
+                Some(&live_frame.view)
+            } else {
+                None
+            };
+            let Some(attachment_view) = prepared.view.as_ref().or(frame_view) else {
+                unreachable!()
+            };
+            let resolve_view = if prepared.resolve {
+                prepared.resolve_target.as_ref().or(frame_view)
+            } else {
+                None
+            };
 
-            color_attachments.push(Some(wgpu::RenderPassColorAttachment {
+            *slot = Some(wgpu::RenderPassColorAttachment {
                 view: attachment_view,
                 resolve_target: resolve_view,
                 ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color {
-                        r: attachment.clear_color[0],
-                        g: attachment.clear_color[1],
-                        b: attachment.clear_color[2],
-                        a: attachment.clear_color[3],
-                    }),
-                    store: if resolve_view.is_none() {
+                    load: attachment.load_op,
+                    store: attachment.store.unwrap_or(if resolve_view.is_none() {
                         wgpu::StoreOp::Store
                     } else {
                         wgpu::StoreOp::Discard
-                    },
+                    }),
                 },
                 depth_slice: None,
-            }));
+            });
         }
 
-        let depth_stencil_attachment =
-            self.depth_stencil_view
-                .as_ref()
-                .map(|tex| wgpu::RenderPassDepthStencilAttachment {
-                    view: tex,
-                    depth_ops: Some(wgpu::Operations {
-                        load: self.depth_load_op,
-                        store: self.depth_store,
-                    }),
-                    stencil_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(0),
-                        store: wgpu::StoreOp::Discard,
-                    }),
-                });
+        let depth_stencil_attachment = self.depth_stencil_attachment(layer);
+        let timestamp_writes = self.timestamp_writes(timestamp_pass);
 
-        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        Ok(encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("fb render pass"),
-            color_attachments: &color_attachments,
+            color_attachments: &color_attachments[..self.color_attachments.len()],
             depth_stencil_attachment,
-            timestamp_writes: None,
-            occlusion_query_set: None,
-        })
+            timestamp_writes,
+            occlusion_query_set: self.occlusion_query_set.as_ref(),
+        }))
     }
 
+    /// Begins a pass with only the depth-stencil attachment.
+    /// Panics if the pass can't be started, see `try_begin_depth_pass`
     pub fn begin_depth_pass<'a>(
         &'a mut self,
         encoder: &'a mut wgpu::CommandEncoder,
     ) -> wgpu::RenderPass<'a> {
-        let depth_stencil_attachment =
-            self.depth_stencil_view
-                .as_ref()
-                .map(|tex| wgpu::RenderPassDepthStencilAttachment {
-                    view: tex,
-                    depth_ops: Some(wgpu::Operations {
-                        load: self.depth_load_op,
-                        store: self.depth_store,
-                    }),
-                    stencil_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(0),
-                        store: wgpu::StoreOp::Discard,
-                    }),
-                });
+        self.begin_depth_pass_layer(encoder, 0)
+    }
 
-        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("fb render pass"),
+    /// Begins a pass with only the depth-stencil attachment, to one of its layers.
+    /// Panics if the pass can't be started, see `try_begin_depth_pass_layer`
+    pub fn begin_depth_pass_layer<'a>(
+        &'a mut self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        layer: u32,
+    ) -> wgpu::RenderPass<'a> {
+        match self.try_begin_depth_pass_layer(encoder, layer) {
+            Ok(pass) => pass,
+            Err(err) => panic!("Framebuffer depth pass failed: {err}"),
+        }
+    }
+
+    /// Begins a pass with only the depth-stencil attachment, such as for a shadow map
+    pub fn try_begin_depth_pass<'a>(
+        &'a mut self,
+        encoder: &'a mut wgpu::CommandEncoder,
+    ) -> Result<wgpu::RenderPass<'a>, FramebufferError> {
+        self.try_begin_depth_pass_layer(encoder, 0)
+    }
+
+    /// Begins a pass with only the depth-stencil attachment, to one of its layers.
+    /// Fails with `NoTexture` when there is no depth-stencil attachment.
+    pub fn try_begin_depth_pass_layer<'a>(
+        &'a mut self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        layer: u32,
+    ) -> Result<wgpu::RenderPass<'a>, FramebufferError> {
+        if !self.is_configured() {
+            return Err(FramebufferError::NotConfigured);
+        }
+        if layer >= self.layer_count {
+            return Err(FramebufferError::LayerOutOfRange(layer));
+        }
+        if self.depth_stencil.is_none() {
+            return Err(FramebufferError::NoTexture);
+        }
+        let timestamp_pass = self.timestamps.as_mut().and_then(|t| t.next_pass());
+        let depth_stencil_attachment = self.depth_stencil_attachment(layer);
+        let timestamp_writes = self.timestamp_writes(timestamp_pass);
+
+        Ok(encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("fb depth pass"),
             color_attachments: &[],
             depth_stencil_attachment,
-            timestamp_writes: None,
-            occlusion_query_set: None,
+            timestamp_writes,
+            occlusion_query_set: self.occlusion_query_set.as_ref(),
+        }))
+    }
+
+    fn timestamp_writes(&self, pass: Option<u32>) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        Some(self.timestamps.as_ref()?.pass_writes(pass?))
+    }
+
+    /// Records resolving the timestamps of the passes begun since the last resolve.
+    /// Call it once per frame after the passes, before submitting.
+    pub fn resolve_timestamps(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if let Some(timestamps) = &mut self.timestamps {
+            timestamps.resolve(encoder);
+        }
+    }
+
+    /// Reads back the resolved timestamps without waiting for the GPU.
+    /// Call it after submitting, the durations become available from
+    /// `pass_durations_ms` after a later submit or device poll.
+    pub fn read_timestamps(&mut self, queue: &wgpu::Queue) {
+        if let Some(timestamps) = &mut self.timestamps {
+            timestamps.read(queue);
+        }
+    }
+
+    /// Returns the GPU durations of the passes of the last read back frame in
+    /// milliseconds, in the order the passes were begun. Empty when timestamps
+    /// are not enabled or supported.
+    pub fn pass_durations_ms(&self) -> &[f64] {
+        self.timestamps
+            .as_ref()
+            .map_or(&[], |timestamps| timestamps.durations_ms())
+    }
+
+    /// Collects the views the passes to the layer use
+    fn prepare_pass(&self, layer: u32) -> PreparedPass {
+        let color_attachments = self
+            .color_attachments
+            .iter()
+            .map(|attachment| prepare_color_attachment(attachment, layer, self.msaa_resolve))
+            .collect();
+        PreparedPass { color_attachments }
+    }
+
+    /// Acquires the next frames of the surface attachments.
+    /// On error none are left live.
+    fn acquire_live_frames(&mut self) -> Result<(), FramebufferError> {
+        for attachment in &self.color_attachments {
+            if let ColorAttachmentData::Surface {
+                surface,
+                config,
+                options,
+                ..
+            } = &attachment.data
+            {
+                let new_frame =
+                    match acquire_surface_texture(surface, config.as_ref(), self.device.as_ref()) {
+                        Ok(frame) => frame,
+                        Err(err) => {
+                            self.live_frame.clear();
+                            return Err(FramebufferError::SurfaceAcquire(err));
+                        }
+                    };
+                let frame_view = new_frame.texture.create_view(&wgpu::TextureViewDescriptor {
+                    format: options.view_format,
+                    ..Default::default()
+                });
+
+                self.live_frame.push(LiveFrame {
+                    frame: new_frame,
+                    view: frame_view,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the view a blit to the attachment renders to, the view it
+    /// resolves to when multisampled, the format they are viewed in, and how
+    /// the rendered view is stored, as the passes store the multisample texture.
+    /// The surface frames are acquired unless they are live already, and
+    /// need to be presented after. A blit that keeps the contents outside
+    /// its rect loads them, so they need to be stored by the passes.
+    pub(crate) fn blit_target(
+        &mut self,
+        attachment: impl AttachmentKey,
+        keep_contents: bool,
+    ) -> Result<
+        (
+            wgpu::TextureView,
+            Option<wgpu::TextureView>,
+            wgpu::TextureFormat,
+            wgpu::StoreOp,
+        ),
+        FramebufferError,
+    > {
+        let idx = attachment
+            .attachment_index(self)
+            .ok_or(FramebufferError::UnknownAttachment)?;
+        if !self.is_configured() {
+            return Err(FramebufferError::NotConfigured);
+        }
+        let color_attachment = &self.color_attachments[idx];
+        let resolved = self.msaa_resolve
+            && color_attachment
+                .configured
+                .as_ref()
+                .is_some_and(|c| c.msaa_view.is_some());
+        let multisample_stored = color_attachment.store == Some(wgpu::StoreOp::Store);
+        if keep_contents && resolved && !multisample_stored {
+            return Err(FramebufferError::MultisampleNotStored);
+        }
+        let store = if resolved && !multisample_stored {
+            wgpu::StoreOp::Discard
+        } else {
+            wgpu::StoreOp::Store
+        };
+        if self.live_frame.is_empty() {
+            self.acquire_live_frames()?;
+        }
+        let prepared = prepare_color_attachment(&self.color_attachments[idx], 0, self.msaa_resolve);
+        let frame_view = if prepared.surface {
+            let surface_index = self.color_attachments[..idx]
+                .iter()
+                .filter(|a| matches!(a.data, ColorAttachmentData::Surface { .. }))
+                .count();
+            let Some(live_frame) = self.live_frame.get(surface_index) else {
+                unreachable!()
+            };
+            Some(live_frame.view.clone())
+        } else {
+            None
+        };
+        let Some(view) = prepared.view.or(frame_view.clone()) else {
+            unreachable!()
+        };
+        let resolve_target = if prepared.resolve {
+            prepared.resolve_target.or(frame_view)
+        } else {
+            None
+        };
+        Ok((
+            view,
+            resolve_target,
+            self.color_attachments[idx].view_format(),
+            store,
+        ))
+    }
+
+    /// The depth-stencil attachment for a pass to the layer, with operations
+    /// only for the aspects the depth-stencil format has
+    fn depth_stencil_attachment(
+        &self,
+        layer: u32,
+    ) -> Option<wgpu::RenderPassDepthStencilAttachment<'_>> {
+        let format = self.depth_stencil_format?;
+        let layer_views = &self.depth_stencil.as_ref()?.layer_views;
+        let view = layer_views.get(layer as usize).or(layer_views.first())?;
+        Some(wgpu::RenderPassDepthStencilAttachment {
+            view,
+            depth_ops: format.has_depth_aspect().then_some(wgpu::Operations {
+                load: self.depth_load_op,
+                store: self.depth_store,
+            }),
+            stencil_ops: format.has_stencil_aspect().then_some(wgpu::Operations {
+                load: self.stencil_load_op,
+                store: self.stencil_store,
+            }),
         })
     }
 
@@ -524,9 +2207,10 @@ impl Framebuffer {
         for attachment in &mut self.color_attachments {
             attachment.configured = None;
         }
+        self.prepared_passes.clear();
     }
     fn invalidate_depth_stencil(&mut self) {
-        self.depth_stencil_view = None;
+        self.depth_stencil = None;
     }
     fn invalidate_resources(&mut self) {
         self.invalidate_color_attachments();
@@ -534,8 +2218,183 @@ impl Framebuffer {
     }
 }
 
+/// Descriptor of a `D2` view of the first mip level of one array layer
+fn layer_view_descriptor(layer: u32) -> wgpu::TextureViewDescriptor<'static> {
+    wgpu::TextureViewDescriptor {
+        label: Some("Framebuffer layer view"),
+        dimension: Some(wgpu::TextureViewDimension::D2),
+        mip_level_count: Some(1),
+        base_array_layer: layer,
+        array_layer_count: Some(1),
+        ..Default::default()
+    }
+}
+
+/// Scales a dimension, keeping it at least 1
+fn scale_dimension(dimension: u32, scale: f32) -> u32 {
+    if dimension == 0 {
+        return 0;
+    }
+    ((dimension as f64 * scale as f64).round() as u32).max(1)
+}
+
+fn to_color(color: &[f64; 4]) -> wgpu::Color {
+    wgpu::Color {
+        r: color[0],
+        g: color[1],
+        b: color[2],
+        a: color[3],
+    }
+}
+
+/// Collects the views of the attachment for passes to the layer
+fn prepare_color_attachment(
+    attachment: &ColorAttachment,
+    layer: u32,
+    resolve: bool,
+) -> PreparedColorAttachment {
+    // All the views were checked with `is_configured` before
+    let Some(configured) = attachment.configured.as_ref() else {
+        unreachable!()
+    };
+    // With a render scale the surface frame is drawn by `upscale`
+    let surface = matches!(attachment.data, ColorAttachmentData::Surface { .. });
+    let output_view = configured.layer_views.get(layer as usize).cloned();
+    match &configured.msaa_view {
+        Some(msaa_view) => PreparedColorAttachment {
+            view: Some(msaa_view.clone()),
+            resolve_target: output_view.filter(|_| resolve),
+            resolve,
+            surface,
+        },
+        None => PreparedColorAttachment {
+            view: output_view,
+            resolve_target: None,
+            resolve: false,
+            surface,
+        },
+    }
+}
+
+/// Acquires the next surface texture. If the surface is outdated or lost,
+/// it is reconfigured with its last configuration and acquired once more.
+fn acquire_surface_texture(
+    surface: &wgpu::Surface<'static>,
+    config: Option<&wgpu::SurfaceConfiguration>,
+    device: Option<&wgpu::Device>,
+) -> Result<wgpu::SurfaceTexture, wgpu::SurfaceError> {
+    match surface.get_current_texture() {
+        Err(err @ (wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost)) => {
+            let (Some(config), Some(device)) = (config, device) else {
+                return Err(err);
+            };
+            surface.configure(device, config);
+            surface.get_current_texture()
+        }
+        result => result,
+    }
+}
+
+/// Returns the features of the format that are usable on the device, as wgpu picks them.
+/// With `TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES`, or on downlevel adapters without
+/// `WEBGPU_TEXTURE_FORMAT_SUPPORT`, they are the adapter's own, otherwise the guaranteed ones.
+/// Without the adapter the guaranteed ones are assumed, unless the feature is enabled
+/// and None is returned.
+fn format_features(
+    adapter: Option<&wgpu::Adapter>,
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+) -> Option<wgpu::TextureFormatFeatures> {
+    let features = device.features();
+    let adapter_specific =
+        features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
+    match adapter {
+        Some(adapter)
+            if adapter_specific
+                || !adapter
+                    .get_downlevel_capabilities()
+                    .flags
+                    .contains(wgpu::DownlevelFlags::WEBGPU_TEXTURE_FORMAT_SUPPORT) =>
+        {
+            Some(adapter.get_texture_format_features(format))
+        }
+        None if adapter_specific => None,
+        _ => Some(format.guaranteed_format_features(features)),
+    }
+}
+
 impl Default for Framebuffer {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_dimension_rounds_and_keeps_one() {
+        assert_eq!(scale_dimension(100, 1.0), 100);
+        assert_eq!(scale_dimension(100, 0.5), 50);
+        assert_eq!(scale_dimension(101, 0.5), 51);
+        assert_eq!(scale_dimension(100, 1.5), 150);
+        assert_eq!(scale_dimension(3, 0.01), 1);
+        assert_eq!(scale_dimension(0, 0.5), 0);
+    }
+
+    #[test]
+    fn same_render_scale_keeps_resources() {
+        let mut fb = Framebuffer::new();
+        fb.set_render_scale(0.5);
+        fb.dirty = false;
+        fb.set_render_scale(0.5);
+        assert!(!fb.needs_configure());
+        fb.set_render_scale(0.75);
+        assert!(fb.needs_configure());
+        assert_eq!(fb.render_scale(), 0.75);
+    }
+
+    #[test]
+    fn attachment_keys() {
+        let mut fb = Framebuffer::new();
+        let hdr = fb.add_named_texture_attachment(
+            "hdr",
+            wgpu::TextureFormat::Rgba16Float,
+            wgpu::TextureUsages::empty(),
+        );
+        let ldr = fb.add_texture_attachment(wgpu::TextureFormat::Rgba8Unorm);
+        assert_eq!(
+            fb.attachment_format("hdr"),
+            Some(wgpu::TextureFormat::Rgba16Float)
+        );
+        assert_eq!(
+            fb.attachment_view_format(ldr),
+            Some(wgpu::TextureFormat::Rgba8Unorm)
+        );
+        fb.set_attachment_name(ldr, "ldr").unwrap();
+        assert_eq!(fb.attachment_id("ldr"), Some(ldr));
+        fb.set_attachment_store("hdr", false).unwrap();
+        fb.set_attachment_mipmapped(hdr, true).unwrap();
+        fb.set_resolution(16, 16);
+        assert_eq!(fb.attachment_mip_level_count(hdr), Some(5));
+        assert_eq!(fb.attachment_mip_level_count(1), Some(1));
+        assert_eq!(fb.surface_options(hdr), None);
+
+        assert!(matches!(
+            fb.set_attachment_load_op("missing", wgpu::LoadOp::Load),
+            Err(FramebufferError::UnknownAttachment)
+        ));
+        assert!(matches!(
+            fb.set_attachment_clear_color(2, &[0.0; 4]),
+            Err(FramebufferError::UnknownAttachment)
+        ));
+        assert!(matches!(
+            fb.set_surface_options(hdr, SurfaceOptions::default()),
+            Err(FramebufferError::NotASurface)
+        ));
+        assert_eq!(fb.attachment_format(2), None);
+        assert_eq!(fb.attachment_mip_level_count("missing"), None);
+        assert_eq!(fb.alpha_mode("missing"), None);
+    }
+}
@@ -0,0 +1,93 @@
+/// Vertex shader of a triangle covering the whole target, prepended to the
+/// fragment shaders of the fullscreen passes
+const VERTEX_SHADER: &str = r#"
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+// Fullscreen triangle, with uv from 0 at the top left to 1 at the bottom right of the target
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+"#;
+
+/// Target a fullscreen pipeline renders to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct FullscreenTarget {
+    pub(crate) format: wgpu::TextureFormat,
+    pub(crate) sample_count: u32,
+    pub(crate) depth_stencil_format: Option<wgpu::TextureFormat>,
+}
+
+impl FullscreenTarget {
+    /// Single sampled target without depth-stencil
+    pub(crate) fn color(format: wgpu::TextureFormat) -> FullscreenTarget {
+        FullscreenTarget {
+            format,
+            sample_count: 1,
+            depth_stencil_format: None,
+        }
+    }
+}
+
+/// Creates the module of the fragment shader, that has a
+/// `fs_main(in: VertexOutput) -> @location(0) vec4<f32>` entry point
+pub(crate) fn shader_module(
+    device: &wgpu::Device,
+    label: &str,
+    fragment_shader: &str,
+) -> wgpu::ShaderModule {
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(format!("{VERTEX_SHADER}\n{fragment_shader}").into()),
+    })
+}
+
+/// Creates the pipeline of a pass drawing 3 vertices with the module of `shader_module`.
+/// A depth-stencil attachment of the target is neither tested nor written.
+pub(crate) fn pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    target: FullscreenTarget,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: Some("fs_main"),
+            compilation_options: Default::default(),
+            targets: &[Some(target.format.into())],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: target
+            .depth_stencil_format
+            .map(|format| wgpu::DepthStencilState {
+                format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+        multisample: wgpu::MultisampleState {
+            count: target.sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+        cache: None,
+    })
+}
@@ -0,0 +1,32 @@
+/// Tightly packed pixels of a texture, such as read back from a `Framebuffer` attachment
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    /// Rows of `width * bytes_per_pixel` bytes, without any padding
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    /// Returns the size of one pixel in bytes
+    pub fn bytes_per_pixel(&self) -> u32 {
+        self.format
+            .block_copy_size(None)
+            .expect("Image format without a copyable block size")
+    }
+
+    /// Returns the bytes of row `y`
+    pub fn row(&self, y: u32) -> &[u8] {
+        let row_bytes = (self.width * self.bytes_per_pixel()) as usize;
+        let start = y as usize * row_bytes;
+        &self.data[start..start + row_bytes]
+    }
+
+    /// Returns the bytes of the pixel at `x`, `y`
+    pub fn pixel(&self, x: u32, y: u32) -> &[u8] {
+        let bpp = self.bytes_per_pixel() as usize;
+        let start = x as usize * bpp;
+        &self.row(y)[start..start + bpp]
+    }
+}
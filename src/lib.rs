@@ -1,9 +1,45 @@
 #[cfg(feature = "async")]
 mod async_block;
 
+#[cfg(feature = "framebuffer")]
+mod blit;
+
 #[cfg(feature = "framebuffer")]
 mod framebuffer;
 
+#[cfg(feature = "framebuffer")]
+mod fullscreen;
+
+#[cfg(all(feature = "testing", not(target_arch = "wasm32")))]
+mod decode;
+
+#[cfg(feature = "framebuffer")]
+mod descriptor;
+
+#[cfg(feature = "framebuffer")]
+mod encode;
+
+#[cfg(feature = "framebuffer")]
+mod image;
+
+#[cfg(feature = "framebuffer")]
+mod mipmap;
+
+#[cfg(feature = "framebuffer")]
+mod postprocess;
+
+#[cfg(feature = "framebuffer")]
+mod readback;
+
+#[cfg(feature = "framebuffer")]
+mod timestamps;
+
+#[cfg(feature = "framebuffer")]
+mod windows;
+
+#[cfg(all(feature = "testing", not(target_arch = "wasm32")))]
+pub mod testing;
+
 /// Re-export the dependent wgpu version, for easily using the same version
 pub use wgpu;
 
@@ -11,4 +47,22 @@ pub use wgpu;
 pub use async_block::block_on;
 
 #[cfg(feature = "framebuffer")]
-pub use framebuffer::Framebuffer;
+pub use blit::{BlitOptions, BlitRect, Blitter};
+
+#[cfg(feature = "framebuffer")]
+pub use framebuffer::{AttachmentId, AttachmentKey, Framebuffer, FramebufferError, SurfaceOptions};
+
+#[cfg(feature = "framebuffer")]
+pub use descriptor::{AttachmentDescriptor, FramebufferDescriptor};
+
+#[cfg(feature = "framebuffer")]
+pub use encode::ImageFileFormat;
+
+#[cfg(feature = "framebuffer")]
+pub use image::Image;
+
+#[cfg(feature = "framebuffer")]
+pub use postprocess::PostProcess;
+
+#[cfg(feature = "framebuffer")]
+pub use windows::WindowFramebuffers;
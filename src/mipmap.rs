@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+
+use crate::fullscreen::{self, FullscreenTarget};
+
+const SHADER: &str = r#"
+@group(0) @binding(0) var source: texture_2d<f32>;
+
+// 2x2 box filter. Loads instead of samples, so that non-filterable formats work too
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let last = textureDimensions(source) - vec2<u32>(1u);
+    let base = vec2<u32>(in.position.xy) * 2u;
+    return (textureLoad(source, min(base, last), 0)
+        + textureLoad(source, min(base + vec2<u32>(1u, 0u), last), 0)
+        + textureLoad(source, min(base + vec2<u32>(0u, 1u), last), 0)
+        + textureLoad(source, min(base + vec2<u32>(1u, 1u), last), 0)) * 0.25;
+}
+"#;
+
+/// Downsamples the mip chains of textures with render passes,
+/// so it works for formats without storage support
+#[derive(Debug)]
+pub(crate) struct MipGenerator {
+    device: wgpu::Device,
+    shader: wgpu::ShaderModule,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    /// Pipelines are created on first use for each format
+    pipelines: HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>,
+}
+
+impl MipGenerator {
+    pub(crate) fn new(device: &wgpu::Device) -> MipGenerator {
+        let shader = fullscreen::shader_module(device, "Framebuffer mip generation shader", SHADER);
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Framebuffer mip generation bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Framebuffer mip generation pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        MipGenerator {
+            device: device.clone(),
+            shader,
+            bind_group_layout,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Records passes that fill mip levels 1.. of every layer from the level above.
+    /// The texture needs `RENDER_ATTACHMENT` and `TEXTURE_BINDING` usages, and
+    /// a float sample type.
+    pub(crate) fn generate(&mut self, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) {
+        let format = texture.format();
+        let pipeline = self.pipelines.entry(format).or_insert_with(|| {
+            fullscreen::pipeline(
+                &self.device,
+                "Framebuffer mip generation pipeline",
+                &self.pipeline_layout,
+                &self.shader,
+                FullscreenTarget::color(format),
+            )
+        });
+
+        let mip_view = |layer, mip_level| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("Framebuffer mip view"),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_mip_level: mip_level,
+                mip_level_count: Some(1),
+                base_array_layer: layer,
+                array_layer_count: Some(1),
+                ..Default::default()
+            })
+        };
+        for layer in 0..texture.depth_or_array_layers() {
+            for mip_level in 1..texture.mip_level_count() {
+                let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Framebuffer mip generation bind group"),
+                    layout: &self.bind_group_layout,
+                    entries: &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&mip_view(
+                            layer,
+                            mip_level - 1,
+                        )),
+                    }],
+                });
+                let target = mip_view(layer, mip_level);
+                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Framebuffer mip generation pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &target,
+                        depth_slice: None,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+                pass.set_pipeline(pipeline);
+                pass.set_bind_group(0, &bind_group, &[]);
+                pass.draw(0..3, 0..1);
+            }
+        }
+    }
+}
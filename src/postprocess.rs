@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+
+use crate::fullscreen::{self, FullscreenTarget};
+use crate::{AttachmentKey, Framebuffer, FramebufferError};
+
+/// Prepended to the fragment shaders of the stages
+const PRELUDE: &str = r#"
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+"#;
+
+#[derive(Debug)]
+struct Stage {
+    label: String,
+    shader: wgpu::ShaderModule,
+    /// Format of the intermediate texture the stage writes, when not the last stage
+    format: wgpu::TextureFormat,
+    /// Pipelines are created on first use for each target
+    pipelines: HashMap<FullscreenTarget, wgpu::RenderPipeline>,
+}
+
+#[derive(Debug)]
+struct Intermediate {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+/// A chain of fullscreen passes, such as tonemapping, bloom composition and FXAA.
+///
+/// Each stage is a WGSL fragment shader that reads the previous stage's result and
+/// writes the next one. The first stage reads an attachment of a `Framebuffer`, and
+/// the last one renders to another `Framebuffer`, typically of the surface.
+/// The stages in between render to intermediate textures, that follow the render
+/// size of the source framebuffer, so they are resized after its `set_resolution`.
+///
+/// The fragment shaders are given
+/// ```wgsl
+/// @group(0) @binding(0) var source: texture_2d<f32>;
+/// @group(0) @binding(1) var source_sampler: sampler;
+///
+/// struct VertexOutput {
+///     @builtin(position) position: vec4<f32>,
+///     @location(0) uv: vec2<f32>,
+/// };
+/// ```
+/// and need a `fs_main(in: VertexOutput) -> @location(0) vec4<f32>` entry point.
+///
+/// ```rust,no_run
+///    # let (device, mut encoder) : (wgpu::Device, wgpu::CommandEncoder) = unimplemented!();
+///    # let (scene, mut output) : (wgpu_misc::Framebuffer, wgpu_misc::Framebuffer) = unimplemented!();
+///    let mut post = wgpu_misc::PostProcess::new(&device);
+///    post.add_stage(
+///        "invert",
+///        "@fragment
+///        fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+///            let color = textureSample(source, source_sampler, in.uv);
+///            return vec4<f32>(1.0 - color.rgb, color.a);
+///        }",
+///        wgpu::TextureFormat::Rgba16Float,
+///    );
+///    post.run(&mut encoder, &scene, "color", &mut output)
+///        .expect("Post-processing");
+/// ```
+#[derive(Debug)]
+pub struct PostProcess {
+    device: wgpu::Device,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    sampler: wgpu::Sampler,
+    stages: Vec<Stage>,
+    /// Targets of all but the last stage
+    intermediates: Vec<Intermediate>,
+}
+
+impl PostProcess {
+    pub fn new(device: &wgpu::Device) -> PostProcess {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("PostProcess bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("PostProcess pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("PostProcess sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        PostProcess {
+            device: device.clone(),
+            bind_group_layout,
+            pipeline_layout,
+            sampler,
+            stages: Vec::new(),
+            intermediates: Vec::new(),
+        }
+    }
+
+    /// Appends a stage. `format` is the format of the intermediate texture it
+    /// writes, unused while it is the last stage.
+    pub fn add_stage(&mut self, label: &str, fragment_shader: &str, format: wgpu::TextureFormat) {
+        let shader = fullscreen::shader_module(
+            &self.device,
+            label,
+            &format!("{PRELUDE}\n{fragment_shader}"),
+        );
+        self.stages.push(Stage {
+            label: label.to_owned(),
+            shader,
+            format,
+            pipelines: HashMap::new(),
+        });
+        // The previous last stage needs a target now
+        self.intermediates.clear();
+    }
+
+    /// Returns the number of stages
+    pub fn stage_count(&self) -> usize {
+        self.stages.len()
+    }
+
+    /// Removes all the stages
+    pub fn clear(&mut self) {
+        self.stages.clear();
+        self.intermediates.clear();
+    }
+
+    /// Records the passes of the stages, from the attachment of `source` to
+    /// `output`, that needs to have a single color attachment. A depth-stencil
+    /// attachment of the output is loaded and stored by its own operations, so
+    /// by default it is cleared, but the last stage neither tests nor writes it.
+    /// Remember to `present` the output after the passes are submitted.
+    /// Nothing is recorded without stages.
+    pub fn run(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &Framebuffer,
+        source_attachment: impl AttachmentKey,
+        output: &mut Framebuffer,
+    ) -> Result<(), FramebufferError> {
+        let PostProcess {
+            device,
+            bind_group_layout,
+            pipeline_layout,
+            sampler,
+            stages,
+            intermediates,
+        } = self;
+        let Some((last, stages)) = stages.split_last_mut() else {
+            return Ok(());
+        };
+        let source_idx = source_attachment
+            .attachment_index(source)
+            .ok_or(FramebufferError::UnknownAttachment)?;
+        let source_view = source
+            .attachment_view(source_idx)
+            .ok_or(FramebufferError::NoTexture)?;
+        let count = output.color_attachment_count();
+        let (1, Some(output_format)) = (count, output.attachment_view_format(0)) else {
+            return Err(FramebufferError::AttachmentCount(count));
+        };
+
+        let size = wgpu::Extent3d {
+            width: source.render_width(),
+            height: source.render_height(),
+            depth_or_array_layers: 1,
+        };
+        if intermediates
+            .first()
+            .is_some_and(|intermediate| intermediate.texture.size() != size)
+        {
+            intermediates.clear();
+        }
+        if intermediates.is_empty() {
+            *intermediates = stages
+                .iter()
+                .map(|stage| {
+                    let texture = device.create_texture(&wgpu::TextureDescriptor {
+                        label: Some(&stage.label),
+                        size,
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: wgpu::TextureDimension::D2,
+                        format: stage.format,
+                        usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                            | wgpu::TextureUsages::TEXTURE_BINDING,
+                        view_formats: &[],
+                    });
+                    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                    Intermediate { texture, view }
+                })
+                .collect();
+        }
+
+        let mut input = source_view;
+        let bind_group = |input| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("PostProcess bind group"),
+                layout: bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(input),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                ],
+            })
+        };
+        for (stage, intermediate) in stages.iter_mut().zip(intermediates.iter()) {
+            let label = stage.label.clone();
+            let pipeline = stage.pipeline(
+                device,
+                pipeline_layout,
+                FullscreenTarget::color(stage.format),
+            );
+            let bind_group = bind_group(input);
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(&label),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &intermediate.view,
+                    depth_slice: None,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.draw(0..3, 0..1);
+            input = &intermediate.view;
+        }
+
+        let target = FullscreenTarget {
+            format: output_format,
+            sample_count: output.sample_count(),
+            depth_stencil_format: output.depth_stencil_format(),
+        };
+        let pipeline = last.pipeline(device, pipeline_layout, target);
+        let bind_group = bind_group(input);
+        let mut pass = output.try_begin_render_pass(encoder)?;
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+        Ok(())
+    }
+}
+
+impl Stage {
+    /// Returns the pipeline for the target, creating it on first use
+    fn pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        target: FullscreenTarget,
+    ) -> &wgpu::RenderPipeline {
+        let Stage {
+            label,
+            shader,
+            pipelines,
+            ..
+        } = self;
+        pipelines
+            .entry(target)
+            .or_insert_with(|| fullscreen::pipeline(device, label, layout, shader, target))
+    }
+}
@@ -0,0 +1,233 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::{FramebufferError, Image};
+
+/// Texture contents copied to a mappable buffer, with rows padded to
+/// `COPY_BYTES_PER_ROW_ALIGNMENT`
+pub(crate) struct Staging {
+    buffer: wgpu::Buffer,
+    submission: wgpu::SubmissionIndex,
+    bytes_per_row: u32,
+    padded_bytes_per_row: u32,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+}
+
+impl Staging {
+    /// Copies the first mip level of a layer of a texture that has `COPY_SRC` usage.
+    /// The copy is submitted right away.
+    pub(crate) fn new(
+        texture: &wgpu::Texture,
+        layer: u32,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<Staging, FramebufferError> {
+        let format = texture.format();
+        if !texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+            return Err(FramebufferError::MissingUsage(
+                wgpu::TextureUsages::COPY_SRC,
+            ));
+        }
+        // Depth-stencil and compressed formats would need per aspect or per block handling
+        let bytes_per_pixel = format
+            .block_copy_size(None)
+            .filter(|_| format.block_dimensions() == (1, 1) && texture.sample_count() == 1)
+            .ok_or(FramebufferError::UnsupportedFormat(format))?;
+
+        let size = texture.size();
+        if layer >= size.depth_or_array_layers {
+            return Err(FramebufferError::LayerOutOfRange(layer));
+        }
+        let bytes_per_row = size.width * bytes_per_pixel;
+        let padded_bytes_per_row =
+            bytes_per_row.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Framebuffer readback buffer"),
+            size: padded_bytes_per_row as u64 * size.height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Framebuffer readback"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer,
+                },
+                ..texture.as_image_copy()
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        let submission = queue.submit(Some(encoder.finish()));
+
+        Ok(Staging {
+            buffer,
+            submission,
+            bytes_per_row,
+            padded_bytes_per_row,
+            width: size.width,
+            height: size.height,
+            format,
+        })
+    }
+
+    /// Maps the buffer and returns the pixels without the row padding
+    pub(crate) async fn read(self, device: &wgpu::Device) -> Result<Image, FramebufferError> {
+        map_buffer(&self.buffer, self.submission, device).await?;
+
+        let bytes_per_row = self.bytes_per_row as usize;
+        let mut data = Vec::with_capacity(bytes_per_row * self.height as usize);
+        {
+            let mapped = self.buffer.slice(..).get_mapped_range();
+            for row in mapped.chunks_exact(self.padded_bytes_per_row as usize) {
+                data.extend_from_slice(&row[..bytes_per_row]);
+            }
+        }
+        self.buffer.unmap();
+
+        Ok(Image {
+            width: self.width,
+            height: self.height,
+            format: self.format,
+            data,
+        })
+    }
+}
+
+/// Query results copied to a mappable buffer
+pub(crate) struct QueryStaging {
+    buffer: wgpu::Buffer,
+    submission: wgpu::SubmissionIndex,
+}
+
+impl QueryStaging {
+    /// Resolves the queries and copies them for reading.
+    /// The copy is submitted right away.
+    pub(crate) fn new(
+        query_set: &wgpu::QuerySet,
+        queries: std::ops::Range<u32>,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> QueryStaging {
+        let size = queries.len() as u64 * wgpu::QUERY_SIZE as u64;
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Framebuffer query resolve buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Framebuffer query readback buffer"),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Framebuffer query readback"),
+        });
+        encoder.resolve_query_set(query_set, queries, &resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&resolve_buffer, 0, &buffer, 0, size);
+        let submission = queue.submit(Some(encoder.finish()));
+
+        QueryStaging { buffer, submission }
+    }
+
+    /// Maps the buffer and returns the query results
+    pub(crate) async fn read(self, device: &wgpu::Device) -> Result<Vec<u64>, FramebufferError> {
+        map_buffer(&self.buffer, self.submission, device).await?;
+
+        let results = self
+            .buffer
+            .slice(..)
+            .get_mapped_range()
+            .chunks_exact(wgpu::QUERY_SIZE as usize)
+            .map(|result| u64::from_le_bytes(result.try_into().unwrap()))
+            .collect();
+        self.buffer.unmap();
+        Ok(results)
+    }
+}
+
+/// Maps the whole buffer for reading, once the submission has finished
+async fn map_buffer(
+    buffer: &wgpu::Buffer,
+    submission: wgpu::SubmissionIndex,
+    device: &wgpu::Device,
+) -> Result<(), FramebufferError> {
+    let state = Arc::new(Mutex::new(MapState::default()));
+    {
+        let state = state.clone();
+        buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let mut state = state.lock().unwrap();
+                state.result = Some(result);
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            });
+    }
+
+    // On web the callback is invoked from the event loop instead
+    #[cfg(not(target_arch = "wasm32"))]
+    device
+        .poll(wgpu::PollType::Wait {
+            submission_index: Some(submission),
+            timeout: None,
+        })
+        .map_err(FramebufferError::DevicePoll)?;
+    #[cfg(target_arch = "wasm32")]
+    let _ = (device, submission);
+
+    MapFuture { state }
+        .await
+        .map_err(FramebufferError::BufferMap)
+}
+
+#[derive(Default)]
+struct MapState {
+    result: Option<Result<(), wgpu::BufferAsyncError>>,
+    waker: Option<Waker>,
+}
+
+/// Resolves when the `map_async` callback has been called
+struct MapFuture {
+    state: Arc<Mutex<MapState>>,
+}
+
+impl Future for MapFuture {
+    type Output = Result<(), wgpu::BufferAsyncError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
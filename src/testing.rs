@@ -0,0 +1,384 @@
+//! Headless golden image tests for render passes.
+//!
+//! A `RenderTest` renders into a texture `Framebuffer` on a shared device,
+//! reads the result back and compares it to a reference PNG with a per-channel
+//! tolerance. On mismatch the rendered image and a diff image are written to
+//! the output directory.
+//!
+//! ```rust,no_run
+//! #[test]
+//! fn clear_to_red() {
+//!     let device = &wgpu_misc::testing::context().device;
+//!     // .. create pipelines with the device
+//!     wgpu_misc::assert_render_matches!("clear_to_red", |pass| {
+//!         // .. draw with pass
+//!     });
+//! }
+//! ```
+//!
+//! References are read from `tests/reference/<name>.png` of the crate using
+//! the macro. Running with the environment variable `WGPU_MISC_UPDATE_REFERENCES`
+//! set writes the rendered images as the new references.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::decode::decode_png;
+use crate::{Framebuffer, FramebufferError, Image, ImageFileFormat};
+
+/// Environment variable that makes render tests write their references
+pub const UPDATE_REFERENCES_ENV: &str = "WGPU_MISC_UPDATE_REFERENCES";
+
+/// Device shared by the render tests
+#[derive(Debug)]
+pub struct TestContext {
+    pub instance: wgpu::Instance,
+    pub adapter: wgpu::Adapter,
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+}
+
+impl TestContext {
+    /// Creates a device on the fallback (software) adapter if there is one,
+    /// otherwise on any available adapter. The backends can be picked with
+    /// the `WGPU_BACKEND` environment variable.
+    pub async fn new() -> Result<TestContext, Box<dyn std::error::Error + Send + Sync>> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::from_env_or_default());
+        let mut options = wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::LowPower,
+            force_fallback_adapter: true,
+            compatible_surface: None,
+        };
+        let adapter = match instance.request_adapter(&options).await {
+            Ok(adapter) => adapter,
+            Err(_) => {
+                options.force_fallback_adapter = false;
+                instance.request_adapter(&options).await?
+            }
+        };
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: Some("wgpu-misc test device"),
+                required_limits: adapter.limits(),
+                ..Default::default()
+            })
+            .await?;
+        Ok(TestContext {
+            instance,
+            adapter,
+            device,
+            queue,
+        })
+    }
+}
+
+/// Returns the device shared by all the render tests of the process.
+/// Panics if no adapter is available.
+pub fn context() -> &'static TestContext {
+    static CONTEXT: OnceLock<TestContext> = OnceLock::new();
+    CONTEXT.get_or_init(|| {
+        crate::block_on(TestContext::new())
+            .unwrap_or_else(|err| panic!("Creating the test device failed: {err}"))
+    })
+}
+
+/// Reasons a `RenderTest` did not match its reference
+#[derive(Debug)]
+pub enum RenderMismatch {
+    /// Rendering, reading back or writing the images failed
+    Framebuffer(FramebufferError),
+    /// There is no reference image, the rendered image is written to the path
+    MissingReference { reference: PathBuf, actual: PathBuf },
+    /// The reference can't be decoded, or has a different size or channel count
+    IncompatibleReference(PathBuf),
+    /// Pixels differ more than the tolerance
+    Pixels {
+        count: usize,
+        max_difference: u8,
+        actual: PathBuf,
+        diff: PathBuf,
+    },
+}
+
+impl std::fmt::Display for RenderMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderMismatch::Framebuffer(err) => write!(f, "{err}"),
+            RenderMismatch::MissingReference { reference, actual } => write!(
+                f,
+                "Reference {} is missing, the rendered image is at {}. \
+                 Set {UPDATE_REFERENCES_ENV} to accept it.",
+                reference.display(),
+                actual.display()
+            ),
+            RenderMismatch::IncompatibleReference(reference) => write!(
+                f,
+                "Reference {} can't be compared with the rendered image",
+                reference.display()
+            ),
+            RenderMismatch::Pixels {
+                count,
+                max_difference,
+                actual,
+                diff,
+            } => write!(
+                f,
+                "{count} pixels differ by up to {max_difference}, \
+                 see {} and {}",
+                actual.display(),
+                diff.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RenderMismatch {}
+
+impl From<FramebufferError> for RenderMismatch {
+    fn from(err: FramebufferError) -> Self {
+        RenderMismatch::Framebuffer(err)
+    }
+}
+
+/// A render pass test compared against a reference image
+#[derive(Debug, Clone)]
+pub struct RenderTest {
+    name: String,
+    width: u32,
+    height: u32,
+    format: wgpu::TextureFormat,
+    sample_count: u32,
+    depth_stencil_format: Option<wgpu::TextureFormat>,
+    clear_color: [f64; 4],
+    tolerance: u8,
+    reference_dir: Option<PathBuf>,
+    output_dir: PathBuf,
+}
+
+impl RenderTest {
+    /// Creates a 64x64 `Rgba8UnormSrgb` test, cleared to transparent black,
+    /// with a tolerance of 2 per channel
+    pub fn new(name: impl Into<String>) -> RenderTest {
+        RenderTest {
+            name: name.into(),
+            width: 64,
+            height: 64,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            sample_count: 1,
+            depth_stencil_format: None,
+            clear_color: [0.0; 4],
+            tolerance: 2,
+            reference_dir: None,
+            output_dir: std::env::temp_dir().join("wgpu-misc-render-tests"),
+        }
+    }
+
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// Sets the color format, which needs to be an 8 bit format `Image::encode` supports
+    pub fn format(mut self, format: wgpu::TextureFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    pub fn depth_stencil_format(mut self, format: Option<wgpu::TextureFormat>) -> Self {
+        self.depth_stencil_format = format;
+        self
+    }
+
+    pub fn clear_color(mut self, clear_color: [f64; 4]) -> Self {
+        self.clear_color = clear_color;
+        self
+    }
+
+    /// Sets the largest allowed difference per channel
+    pub fn tolerance(mut self, tolerance: u8) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Sets the directory of the reference images
+    pub fn reference_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.reference_dir = Some(dir.into());
+        self
+    }
+
+    /// Sets the directory of the reference images, unless it is already set
+    pub fn or_reference_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.reference_dir.get_or_insert_with(|| dir.into());
+        self
+    }
+
+    /// Sets the directory the rendered and diff images of failed tests are written to.
+    /// Defaults to `wgpu-misc-render-tests` in the temporary directory
+    pub fn output_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.output_dir = dir.into();
+        self
+    }
+
+    /// Renders a pass with the shared `context` device and reads back the result
+    pub fn render(
+        &self,
+        render: impl FnOnce(&mut wgpu::RenderPass<'_>),
+    ) -> Result<Image, FramebufferError> {
+        let context = context();
+        let mut framebuffer = Framebuffer::new();
+        // The fallback adapters are often downlevel, with format features of their own
+        framebuffer.set_adapter(&context.adapter);
+        let attachment = framebuffer
+            .add_texture_attachment_with_usage(self.format, wgpu::TextureUsages::COPY_SRC);
+        framebuffer.set_resolution(self.width, self.height);
+        framebuffer.set_sample_count(self.sample_count);
+        framebuffer.set_depth_stencil_format(self.depth_stencil_format);
+        framebuffer.set_clear_color(&self.clear_color);
+        framebuffer.try_configure(&context.device)?;
+
+        let mut encoder = context
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some(&self.name),
+            });
+        {
+            let mut pass = framebuffer.try_begin_render_pass(&mut encoder)?;
+            render(&mut pass);
+        }
+        context.queue.submit(Some(encoder.finish()));
+
+        crate::block_on(framebuffer.read_attachment(attachment, &context.device, &context.queue))
+    }
+
+    /// Renders and compares the result against the reference
+    pub fn check(
+        &self,
+        render: impl FnOnce(&mut wgpu::RenderPass<'_>),
+    ) -> Result<(), RenderMismatch> {
+        let image = self.render(render)?;
+        let reference_dir = self
+            .reference_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("tests/reference"));
+        let reference_path = reference_dir.join(format!("{}.png", self.name));
+        let actual_path = self.output_dir.join(format!("{}.png", self.name));
+
+        if std::env::var_os(UPDATE_REFERENCES_ENV).is_some() {
+            write_image(&image, &reference_path)?;
+            return Ok(());
+        }
+
+        let Ok(reference) = std::fs::read(&reference_path) else {
+            write_image(&image, &actual_path)?;
+            return Err(RenderMismatch::MissingReference {
+                reference: reference_path,
+                actual: actual_path,
+            });
+        };
+
+        // Compare in the channel layout the image is encoded with
+        let mut actual = Vec::new();
+        image.encode(ImageFileFormat::Png, &mut actual)?;
+        let (Some(reference), Some(actual)) = (decode_png(&reference), decode_png(&actual)) else {
+            return Err(RenderMismatch::IncompatibleReference(reference_path));
+        };
+        if (reference.width, reference.height, reference.channels)
+            != (actual.width, actual.height, actual.channels)
+        {
+            return Err(RenderMismatch::IncompatibleReference(reference_path));
+        }
+
+        let mut count = 0;
+        let mut max_difference = 0;
+        let mut diff = Vec::with_capacity(image.width as usize * image.height as usize * 4);
+        for (a, r) in actual
+            .data
+            .chunks_exact(actual.channels)
+            .zip(reference.data.chunks_exact(reference.channels))
+        {
+            let difference = a
+                .iter()
+                .zip(r)
+                .map(|(a, r)| a.abs_diff(*r))
+                .max()
+                .unwrap_or(0);
+            max_difference = max_difference.max(difference);
+            if difference > self.tolerance {
+                count += 1;
+                diff.extend_from_slice(&[255, 0, 0, 255]);
+            } else {
+                // Dimmed grayscale of the pixel, to see where the errors are
+                let color = if a.len() >= 3 { &a[..3] } else { &a[..1] };
+                let gray =
+                    (color.iter().map(|&c| c as u32).sum::<u32>() / color.len() as u32 / 4) as u8;
+                diff.extend_from_slice(&[gray, gray, gray, 255]);
+            }
+        }
+        if count == 0 {
+            return Ok(());
+        }
+
+        let diff_path = self.output_dir.join(format!("{}-diff.png", self.name));
+        write_image(&image, &actual_path)?;
+        let diff_image = Image {
+            width: image.width,
+            height: image.height,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            data: diff,
+        };
+        write_image(&diff_image, &diff_path)?;
+        Err(RenderMismatch::Pixels {
+            count,
+            max_difference,
+            actual: actual_path,
+            diff: diff_path,
+        })
+    }
+
+    /// Renders and compares the result against the reference, panicking on mismatch
+    pub fn assert_matches(&self, render: impl FnOnce(&mut wgpu::RenderPass<'_>)) {
+        if let Err(mismatch) = self.check(render) {
+            panic!("Render test {} failed: {mismatch}", self.name);
+        }
+    }
+}
+
+impl From<&str> for RenderTest {
+    fn from(name: &str) -> Self {
+        RenderTest::new(name)
+    }
+}
+
+impl From<String> for RenderTest {
+    fn from(name: String) -> Self {
+        RenderTest::new(name)
+    }
+}
+
+fn write_image(image: &Image, path: &Path) -> Result<(), FramebufferError> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(FramebufferError::Io)?;
+    }
+    image.save(path)
+}
+
+/// Renders a pass and asserts that it matches the reference image
+/// `tests/reference/<name>.png` of the calling crate.
+///
+/// The first argument is a test name or a `RenderTest` with custom settings,
+/// the second a closure drawing with the `&mut wgpu::RenderPass`.
+/// See the `testing` module.
+#[macro_export]
+macro_rules! assert_render_matches {
+    ($test:expr, $render:expr $(,)?) => {
+        $crate::testing::RenderTest::from($test)
+            .or_reference_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/reference"))
+            .assert_matches($render)
+    };
+}
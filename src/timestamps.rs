@@ -0,0 +1,138 @@
+use std::sync::{Arc, Mutex};
+
+/// Begin and end timestamps of the passes of a frame, read back without
+/// waiting for the GPU. The durations arrive a frame or more late.
+#[derive(Debug)]
+pub(crate) struct TimestampQueries {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    max_passes: u32,
+    /// Passes that have been given timestamp writes since the last resolve
+    passes: u32,
+    /// Passes copied to the readback buffer, that is being mapped
+    mapped_passes: Option<u32>,
+    /// Set when the readback buffer was copied to, and needs mapping after submit
+    copied_passes: Option<u32>,
+    /// Set by the `map_async` callback
+    map_result: Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>,
+    period: f32,
+    durations_ms: Vec<f64>,
+}
+
+impl TimestampQueries {
+    pub(crate) fn new(device: &wgpu::Device, max_passes: u32) -> TimestampQueries {
+        let max_passes = max_passes.min(wgpu::QUERY_SET_MAX_QUERIES / 2);
+        let size = max_passes as u64 * 2 * wgpu::QUERY_SIZE as u64;
+        TimestampQueries {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Framebuffer timestamps"),
+                ty: wgpu::QueryType::Timestamp,
+                count: max_passes * 2,
+            }),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Framebuffer timestamp resolve buffer"),
+                size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Framebuffer timestamp readback buffer"),
+                size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+            max_passes,
+            passes: 0,
+            mapped_passes: None,
+            copied_passes: None,
+            map_result: Arc::new(Mutex::new(None)),
+            period: 1.0,
+            durations_ms: Vec::new(),
+        }
+    }
+
+    /// Takes the next pass, None when all are used
+    pub(crate) fn next_pass(&mut self) -> Option<u32> {
+        if self.passes >= self.max_passes {
+            return None;
+        }
+        self.passes += 1;
+        Some(self.passes - 1)
+    }
+
+    /// Returns the timestamp writes for a pass from `next_pass`
+    pub(crate) fn pass_writes(&self, pass: u32) -> wgpu::RenderPassTimestampWrites<'_> {
+        wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(pass * 2),
+            end_of_pass_write_index: Some(pass * 2 + 1),
+        }
+    }
+
+    /// Records resolving the timestamps of the passes so far. They are
+    /// copied for reading back, unless the previous ones are still being read.
+    pub(crate) fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let passes = std::mem::take(&mut self.passes);
+        if passes == 0 {
+            return;
+        }
+        let queries = passes * 2;
+        encoder.resolve_query_set(&self.query_set, 0..queries, &self.resolve_buffer, 0);
+        if self.mapped_passes.is_none() && self.copied_passes.is_none() {
+            encoder.copy_buffer_to_buffer(
+                &self.resolve_buffer,
+                0,
+                &self.readback_buffer,
+                0,
+                queries as u64 * wgpu::QUERY_SIZE as u64,
+            );
+            self.copied_passes = Some(passes);
+        }
+    }
+
+    /// Takes the durations of a finished readback, and starts mapping
+    /// the timestamps resolved since. Call it after submitting.
+    pub(crate) fn read(&mut self, queue: &wgpu::Queue) {
+        if let Some(passes) = self.mapped_passes {
+            let map_result = self.map_result.lock().unwrap().take();
+            match map_result {
+                None => return,
+                Some(Ok(())) => {
+                    let size = passes as u64 * 2 * wgpu::QUERY_SIZE as u64;
+                    let mapped = self.readback_buffer.slice(..size).get_mapped_range();
+                    let period = self.period as f64;
+                    self.durations_ms = mapped
+                        .chunks_exact(2 * wgpu::QUERY_SIZE as usize)
+                        .map(|pair| {
+                            let begin = u64::from_le_bytes(pair[..8].try_into().unwrap());
+                            let end = u64::from_le_bytes(pair[8..].try_into().unwrap());
+                            end.saturating_sub(begin) as f64 * period / 1_000_000.0
+                        })
+                        .collect();
+                    drop(mapped);
+                    self.readback_buffer.unmap();
+                }
+                // The timestamps of the frame are lost, but the next ones can be read
+                Some(Err(_)) => {}
+            }
+            self.mapped_passes = None;
+        }
+
+        if let Some(passes) = self.copied_passes.take() {
+            self.period = queue.get_timestamp_period();
+            let map_result = self.map_result.clone();
+            let size = passes as u64 * 2 * wgpu::QUERY_SIZE as u64;
+            self.readback_buffer
+                .slice(..size)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    *map_result.lock().unwrap() = Some(result);
+                });
+            self.mapped_passes = Some(passes);
+        }
+    }
+
+    pub(crate) fn durations_ms(&self) -> &[f64] {
+        &self.durations_ms
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::hash::Hash;
+
+use crate::{Framebuffer, FramebufferError};
+
+/// Framebuffers of several windows, keyed by a window id such as `winit::window::WindowId`.
+///
+/// Each window has a `Framebuffer` of its own, so they can have different
+/// resolutions. The passes of all of them can be recorded into one encoder,
+/// and the frames presented together after the submit.
+///
+/// ```rust,no_run
+///    # let (window_id, window) : (u32, std::sync::Arc<winit::window::Window>) = unimplemented!();
+///    # let (instance, adapter, device, queue) : (wgpu::Instance, wgpu::Adapter, wgpu::Device, wgpu::Queue) = unimplemented!();
+///    let mut windows = wgpu_misc::WindowFramebuffers::new();
+///    windows
+///        .add_window(window_id, &instance, window, &adapter, &[wgpu::PresentMode::Fifo], &[])
+///        .expect("Surface creation");
+///
+///    // On resize
+///    windows.resize(&window_id, 640, 480);
+///
+///    // On redraw
+///    windows.configure(&device);
+///    let mut encoder = device.create_command_encoder(&Default::default());
+///    for (_id, framebuffer) in windows.iter_mut() {
+///        // Minimized windows are not configured, and are skipped
+///        if let Ok(pass) = framebuffer.try_begin_render_pass(&mut encoder) {
+///            // .. do stuff with pass
+///        }
+///    }
+///    queue.submit(Some(encoder.finish()));
+///    windows.present_all();
+/// ```
+#[derive(Debug)]
+pub struct WindowFramebuffers<K> {
+    framebuffers: HashMap<K, Framebuffer>,
+}
+
+impl<K: Eq + Hash> WindowFramebuffers<K> {
+    pub fn new() -> WindowFramebuffers<K> {
+        WindowFramebuffers {
+            framebuffers: HashMap::new(),
+        }
+    }
+
+    /// Creates a framebuffer for the window, with the format, present mode and
+    /// alpha mode negotiated as in `Framebuffer::new_from_surface_negotiated`.
+    /// It needs a `resize` before it can be configured.
+    /// A previous framebuffer of the id is replaced.
+    pub fn add_window<W: wgpu::WindowHandle + 'static>(
+        &mut self,
+        id: K,
+        instance: &wgpu::Instance,
+        window: std::sync::Arc<W>,
+        adapter: &wgpu::Adapter,
+        present_modes: &[wgpu::PresentMode],
+        alpha_modes: &[wgpu::CompositeAlphaMode],
+    ) -> Result<&mut Framebuffer, FramebufferError> {
+        let framebuffer = Framebuffer::try_new_from_window_negotiated(
+            instance,
+            window,
+            adapter,
+            present_modes,
+            alpha_modes,
+        )?;
+        Ok(self.insert(id, framebuffer))
+    }
+
+    /// Adds a framebuffer created otherwise, replacing a previous one of the id
+    pub fn insert(&mut self, id: K, framebuffer: Framebuffer) -> &mut Framebuffer {
+        match self.framebuffers.entry(id) {
+            Entry::Occupied(mut entry) => {
+                entry.insert(framebuffer);
+                entry.into_mut()
+            }
+            Entry::Vacant(entry) => entry.insert(framebuffer),
+        }
+    }
+
+    /// Removes the framebuffer of a closed window
+    pub fn remove(&mut self, id: &K) -> Option<Framebuffer> {
+        self.framebuffers.remove(id)
+    }
+
+    pub fn get(&self, id: &K) -> Option<&Framebuffer> {
+        self.framebuffers.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &K) -> Option<&mut Framebuffer> {
+        self.framebuffers.get_mut(id)
+    }
+
+    /// Sets the resolution of the framebuffer of a window, if there is one.
+    /// Only it needs to be configured again.
+    pub fn resize(&mut self, id: &K, width: u32, height: u32) {
+        if let Some(framebuffer) = self.framebuffers.get_mut(id) {
+            framebuffer.set_resolution(width, height);
+        }
+    }
+
+    /// Returns the number of windows
+    pub fn len(&self) -> usize {
+        self.framebuffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.framebuffers.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &Framebuffer)> {
+        self.framebuffers.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut Framebuffer)> {
+        self.framebuffers.iter_mut()
+    }
+
+    /// Configures the framebuffers that need it.
+    /// Panics if a configuration is invalid, see `try_configure`
+    pub fn configure(&mut self, device: &wgpu::Device) {
+        if let Err(err) = self.try_configure(device) {
+            panic!("Framebuffer configure failed: {err}");
+        }
+    }
+
+    /// Configures the framebuffers that need it. Minimized windows with a zero
+    /// size are skipped, so that they don't fail the rest.
+    pub fn try_configure(&mut self, device: &wgpu::Device) -> Result<(), FramebufferError> {
+        for framebuffer in self.framebuffers.values_mut() {
+            if framebuffer.needs_configure()
+                && framebuffer.width() != 0
+                && framebuffer.height() != 0
+            {
+                framebuffer.try_configure(device)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Presents the live frames of all the windows.
+    /// Call it after the one submit of the passes of all the windows.
+    pub fn present_all(&mut self) {
+        for framebuffer in self.framebuffers.values_mut() {
+            if framebuffer.needs_present() {
+                framebuffer.present();
+            }
+        }
+    }
+}
+
+impl<K: Eq + Hash> Default for WindowFramebuffers<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
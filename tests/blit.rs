@@ -0,0 +1,166 @@
+use wgpu_misc::testing::context;
+use wgpu_misc::{BlitOptions, BlitRect, Blitter, Framebuffer, FramebufferError, PostProcess, wgpu};
+
+/// Source texture of a row of pixels, red growing from left to right
+fn gradient(width: u32) -> wgpu::TextureView {
+    let context = context();
+    let texture = context.device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Gradient"),
+        size: wgpu::Extent3d {
+            width,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    let data: Vec<u8> = (0..width).flat_map(|x| [x as u8 * 2, 0, 0, 255]).collect();
+    context.queue.write_texture(
+        texture.as_image_copy(),
+        &data,
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(width * 4),
+            rows_per_image: None,
+        },
+        texture.size(),
+    );
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn target(width: u32, height: u32, sample_count: u32) -> Framebuffer {
+    let context = context();
+    let mut fb = Framebuffer::new();
+    fb.set_adapter(&context.adapter);
+    fb.add_texture_attachment_with_usage(
+        wgpu::TextureFormat::Rgba8Unorm,
+        wgpu::TextureUsages::COPY_SRC,
+    );
+    fb.set_resolution(width, height);
+    fb.set_sample_count(sample_count);
+    fb.try_configure(&context.device).unwrap();
+    fb
+}
+
+fn encoder() -> wgpu::CommandEncoder {
+    context()
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor::default())
+}
+
+#[test]
+fn source_and_target_rects() {
+    let context = context();
+    // More rects than fit one uniform buffer
+    let width = 128;
+    let source = gradient(width);
+    let mut fb = target(width, 1, 1);
+    let mut blitter = Blitter::new(&context.device);
+    let mut encoder = encoder();
+    // Mirrors the source, a texel at a time
+    for x in 0..width {
+        let options = BlitOptions::default()
+            .source_rect(BlitRect::new(width - 1 - x, 0, 1, 1))
+            .target_rect(BlitRect::new(x, 0, 1, 1))
+            .filter(wgpu::FilterMode::Nearest);
+        blitter
+            .blit_to_framebuffer(&mut encoder, &context.queue, &source, &mut fb, 0, &options)
+            .unwrap();
+    }
+    context.queue.submit(Some(encoder.finish()));
+
+    let image =
+        wgpu_misc::block_on(fb.read_attachment(0, &context.device, &context.queue)).unwrap();
+    for (x, pixel) in image.data.chunks(4).enumerate() {
+        assert_eq!(pixel, [(width as usize - 1 - x) as u8 * 2, 0, 0, 255]);
+    }
+}
+
+#[test]
+fn unknown_attachment() {
+    let context = context();
+    let source = gradient(4);
+    let mut fb = target(4, 4, 1);
+    let mut blitter = Blitter::new(&context.device);
+    let result = blitter.blit_to_framebuffer(
+        &mut encoder(),
+        &context.queue,
+        &source,
+        &mut fb,
+        "missing",
+        &BlitOptions::default(),
+    );
+    assert!(matches!(result, Err(FramebufferError::UnknownAttachment)));
+
+    let mut post = PostProcess::new(&context.device);
+    post.add_stage(
+        "copy",
+        "@fragment
+        fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+            return textureSample(source, source_sampler, in.uv);
+        }",
+        wgpu::TextureFormat::Rgba8Unorm,
+    );
+    let mut output = target(4, 4, 1);
+    let result = post.run(&mut encoder(), &fb, 1, &mut output);
+    assert!(matches!(result, Err(FramebufferError::UnknownAttachment)));
+}
+
+#[test]
+fn target_rect_needs_stored_multisample_texture() {
+    let context = context();
+    let source = gradient(4);
+    let mut fb = target(4, 4, 4);
+    let mut blitter = Blitter::new(&context.device);
+    let options = BlitOptions::default().target_rect(BlitRect::new(0, 0, 2, 2));
+    let result = blitter.blit_to_framebuffer(
+        &mut encoder(),
+        &context.queue,
+        &source,
+        &mut fb,
+        0,
+        &options,
+    );
+    assert!(matches!(
+        result,
+        Err(FramebufferError::MultisampleNotStored)
+    ));
+
+    // The whole target is cleared, so nothing needs loading
+    fb.set_attachment_store(0, true).unwrap();
+    let mut encoder = encoder();
+    blitter
+        .blit_to_framebuffer(
+            &mut encoder,
+            &context.queue,
+            &source,
+            &mut fb,
+            0,
+            &BlitOptions::default().filter(wgpu::FilterMode::Nearest),
+        )
+        .unwrap();
+    blitter
+        .blit_to_framebuffer(&mut encoder, &context.queue, &source, &mut fb, 0, &options)
+        .unwrap();
+    context.queue.submit(Some(encoder.finish()));
+
+    let image =
+        wgpu_misc::block_on(fb.read_attachment(0, &context.device, &context.queue)).unwrap();
+    for (i, pixel) in image.data.chunks(4).enumerate() {
+        let (x, y) = (i % 4, i / 4);
+        let red = if x < 2 && y < 2 {
+            // The 4 texels filtered to 2 pixels
+            [1, 5][x]
+        } else {
+            x as u8 * 2
+        };
+        assert!(
+            pixel[0].abs_diff(red) <= 1 && pixel[1..] == [0, 0, 255],
+            "Pixel {x}, {y}: {pixel:?}"
+        );
+    }
+}
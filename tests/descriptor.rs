@@ -0,0 +1,146 @@
+use wgpu_misc::testing::context;
+use wgpu_misc::{AttachmentDescriptor, Framebuffer, FramebufferDescriptor, wgpu};
+
+fn gbuffer() -> FramebufferDescriptor {
+    FramebufferDescriptor::new(64, 32)
+        .attachment(
+            AttachmentDescriptor::new(wgpu::TextureFormat::Rgba8Unorm)
+                .name("albedo")
+                .usage(wgpu::TextureUsages::COPY_SRC)
+                .clear_color([0.25, 0.5, 0.75, 1.0]),
+        )
+        .attachment(
+            AttachmentDescriptor::new(wgpu::TextureFormat::Rgba16Float)
+                .name("lighting")
+                .mipmapped(true)
+                .history(true)
+                .load()
+                .store(true),
+        )
+        .depth_stencil_format(wgpu::TextureFormat::Depth32Float)
+        .depth_stencil_usage(wgpu::TextureUsages::TEXTURE_BINDING)
+        .depth_clear_value(0.0)
+        .depth_store(true)
+        .present_mode(wgpu::PresentMode::Fifo)
+}
+
+#[test]
+fn builder_sets_fields() {
+    let attachment = AttachmentDescriptor::new(wgpu::TextureFormat::Rgba8Unorm)
+        .name("color")
+        .usage(wgpu::TextureUsages::COPY_SRC)
+        .mipmapped(true)
+        .history(true)
+        .load()
+        .store(false);
+    assert_eq!(
+        attachment,
+        AttachmentDescriptor {
+            name: Some("color".to_owned()),
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::COPY_SRC,
+            mipmapped: true,
+            history: true,
+            clear_color: [0.0; 4],
+            load: true,
+            store: Some(false),
+        }
+    );
+    // Setting a clear color makes it cleared again
+    assert!(!attachment.clear_color([1.0; 4]).load);
+
+    let descriptor = FramebufferDescriptor::new(1, 1)
+        .size(640, 480)
+        .sample_count(4)
+        .sample_count_fallback(true)
+        .layer_count(6)
+        .cube(true)
+        .stencil_clear_value(3)
+        .stencil_store(true);
+    assert_eq!(
+        descriptor,
+        FramebufferDescriptor {
+            width: 640,
+            height: 480,
+            sample_count: 4,
+            sample_count_fallback: true,
+            layer_count: 6,
+            cube: true,
+            stencil_clear_value: 3,
+            stencil_store: true,
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn clone_and_eq() {
+    let descriptor = gbuffer();
+    let clone = descriptor.clone();
+    assert_eq!(descriptor, clone);
+    assert_ne!(descriptor, clone.clone().sample_count(4));
+    assert_ne!(
+        descriptor,
+        clone.attachment(AttachmentDescriptor::new(wgpu::TextureFormat::R32Float))
+    );
+}
+
+#[test]
+fn from_descriptor() {
+    let context = context();
+    let mut fb = Framebuffer::from_descriptor(&gbuffer());
+    assert_eq!((fb.width(), fb.height()), (64, 32));
+    assert_eq!(fb.sample_count(), 1);
+    assert_eq!(fb.layer_count(), 1);
+    assert!(!fb.is_cube());
+    assert_eq!(fb.color_attachment_count(), 2);
+    assert_eq!(fb.attachment_id("albedo").map(|id| id.index()), Some(0));
+    assert_eq!(fb.attachment_id("lighting").map(|id| id.index()), Some(1));
+    assert_eq!(
+        fb.attachment_format("lighting"),
+        Some(wgpu::TextureFormat::Rgba16Float)
+    );
+    assert_eq!(fb.attachment_mip_level_count("albedo"), Some(1));
+    assert_eq!(fb.attachment_mip_level_count("lighting"), Some(7));
+    assert_eq!(
+        fb.depth_stencil_format(),
+        Some(wgpu::TextureFormat::Depth32Float)
+    );
+    assert_eq!(fb.present_mode(), wgpu::PresentMode::Fifo);
+
+    fb.set_adapter(&context.adapter);
+    fb.try_configure(&context.device).unwrap();
+    let albedo = fb.attachment_texture("albedo").unwrap();
+    assert!(albedo.usage().contains(wgpu::TextureUsages::COPY_SRC));
+    assert!(fb.previous_view("albedo").is_none());
+    assert!(fb.previous_view("lighting").is_some());
+    assert!(
+        fb.depth_stencil_texture()
+            .unwrap()
+            .usage()
+            .contains(wgpu::TextureUsages::TEXTURE_BINDING)
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+    let descriptor = gbuffer().sample_count(4).sample_count_fallback(true);
+    let json = serde_json::to_string(&descriptor).unwrap();
+    let parsed: FramebufferDescriptor = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, descriptor);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_defaults() {
+    let parsed: FramebufferDescriptor = serde_json::from_str(
+        r#"{ "width": 320, "height": 240, "attachments": [{ "format": "rgba8unorm" }] }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        parsed,
+        FramebufferDescriptor::new(320, 240)
+            .attachment(AttachmentDescriptor::new(wgpu::TextureFormat::Rgba8Unorm))
+    );
+}
@@ -0,0 +1,288 @@
+use wgpu_misc::testing::context;
+use wgpu_misc::{BlitOptions, Blitter, Framebuffer, FramebufferError, Image, wgpu};
+
+/// Texture attachment framebuffer for the test device
+fn texture_framebuffer(format: wgpu::TextureFormat) -> Framebuffer {
+    let mut fb = Framebuffer::new_with_texture(format);
+    fb.set_adapter(&context().adapter);
+    fb.set_resolution(16, 16);
+    fb
+}
+
+#[test]
+fn unrenderable_format_is_an_error() {
+    let context = context();
+    // Not renderable on downlevel adapters such as GL
+    let format = wgpu::TextureFormat::Rgba32Float;
+    let renderable = context
+        .adapter
+        .get_texture_format_features(format)
+        .allowed_usages
+        .contains(wgpu::TextureUsages::RENDER_ATTACHMENT);
+    let mut fb = texture_framebuffer(format);
+    match fb.try_configure(&context.device) {
+        Ok(()) => assert!(renderable),
+        Err(FramebufferError::UnsupportedFormat(f)) => {
+            assert!(!renderable);
+            assert_eq!(f, format);
+        }
+        Err(err) => panic!("Unexpected error: {err}"),
+    }
+}
+
+#[test]
+fn storage_usage_follows_adapter() {
+    let context = context();
+    for format in [
+        wgpu::TextureFormat::Rgba8Unorm,
+        wgpu::TextureFormat::Rgba16Float,
+        wgpu::TextureFormat::R32Float,
+    ] {
+        let features = context.adapter.get_texture_format_features(format);
+        if !features
+            .allowed_usages
+            .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+        {
+            continue;
+        }
+        let mut fb = texture_framebuffer(format);
+        fb.try_configure(&context.device).unwrap();
+        let usage = fb.attachment_texture(0).unwrap().usage();
+        if usage.contains(wgpu::TextureUsages::STORAGE_BINDING) {
+            assert!(
+                features
+                    .allowed_usages
+                    .contains(wgpu::TextureUsages::STORAGE_BINDING),
+                "{format:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn render_scale_sizes() {
+    let device = &context().device;
+    let mut fb = texture_framebuffer(wgpu::TextureFormat::Rgba8Unorm);
+    fb.set_resolution(101, 60);
+    fb.set_depth_stencil_format(Some(wgpu::TextureFormat::Depth32Float));
+    fb.set_render_scale(0.5);
+    fb.try_configure(device).unwrap();
+    assert_eq!((fb.render_width(), fb.render_height()), (51, 30));
+    assert_eq!((fb.width(), fb.height()), (101, 60));
+    let size = |texture: &wgpu::Texture| (texture.width(), texture.height());
+    assert_eq!(size(fb.attachment_texture(0).unwrap()), (51, 30));
+    assert_eq!(size(fb.depth_stencil_texture().unwrap()), (51, 30));
+
+    // Setting the same scale keeps the textures
+    fb.set_render_scale(0.5);
+    assert!(!fb.needs_configure());
+
+    fb.set_render_scale(2.0);
+    fb.try_configure(device).unwrap();
+    assert_eq!(size(fb.attachment_texture(0).unwrap()), (202, 120));
+    assert_eq!(size(fb.depth_stencil_texture().unwrap()), (202, 120));
+}
+
+#[test]
+fn size_over_device_limit() {
+    let device = &context().device;
+    let max = device.limits().max_texture_dimension_2d;
+    let mut fb = texture_framebuffer(wgpu::TextureFormat::Rgba8Unorm);
+    fb.set_resolution(max + 1, 16);
+    assert!(matches!(
+        fb.try_configure(device),
+        Err(FramebufferError::TooLarge)
+    ));
+
+    // The scaled size is checked too
+    fb.set_resolution(16, max / 2 + 1);
+    fb.set_render_scale(2.0);
+    assert!(matches!(
+        fb.try_configure(device),
+        Err(FramebufferError::TooLarge)
+    ));
+    fb.set_render_scale(1.0);
+    fb.set_resolution(16, 16);
+    fb.try_configure(device).unwrap();
+}
+
+/// Reads back a view through a blit to a texture of the size, after recording the encoder
+fn read_view(view: &wgpu::TextureView, size: u32, mut encoder: wgpu::CommandEncoder) -> Image {
+    let context = context();
+    let mut readback = Framebuffer::new();
+    readback.add_texture_attachment_with_usage(
+        wgpu::TextureFormat::Rgba8Unorm,
+        wgpu::TextureUsages::COPY_SRC,
+    );
+    readback.set_resolution(size, size);
+    readback.try_configure(&context.device).unwrap();
+    Blitter::new(&context.device)
+        .blit_to_framebuffer(
+            &mut encoder,
+            &context.queue,
+            view,
+            &mut readback,
+            0,
+            &BlitOptions::default().filter(wgpu::FilterMode::Nearest),
+        )
+        .unwrap();
+    context.queue.submit(Some(encoder.finish()));
+    wgpu_misc::block_on(readback.read_attachment(0, &context.device, &context.queue)).unwrap()
+}
+
+/// Reads back a mip level of the first attachment
+fn read_mip(fb: &Framebuffer, encoder: wgpu::CommandEncoder, level: u32) -> Image {
+    let view = fb
+        .attachment_texture(0)
+        .unwrap()
+        .create_view(&wgpu::TextureViewDescriptor {
+            base_mip_level: level,
+            mip_level_count: Some(1),
+            ..Default::default()
+        });
+    read_view(&view, fb.width() >> level, encoder)
+}
+
+#[test]
+fn mips_are_generated() {
+    let context = context();
+    let mut fb = Framebuffer::new();
+    fb.set_adapter(&context.adapter);
+    fb.add_texture_attachment_with_usage(
+        wgpu::TextureFormat::Rgba8Unorm,
+        wgpu::TextureUsages::COPY_DST,
+    );
+    fb.set_attachment_mipmapped(0, true).unwrap();
+    fb.set_resolution(16, 16);
+    fb.try_configure(&context.device).unwrap();
+    assert_eq!(fb.attachment_mip_level_count(0), Some(5));
+
+    // Checkerboard of 2x2 squares, so the first mip is a checkerboard of
+    // single pixels and the second is the average of black and white
+    let square = |x: u32, y: u32| (x / 2 + y / 2) % 2 == 1;
+    let data: Vec<u8> = (0..16 * 16)
+        .flat_map(|i| {
+            let value = if square(i % 16, i / 16) { 255 } else { 0 };
+            [value, value, value, 255]
+        })
+        .collect();
+    let texture = fb.attachment_texture(0).unwrap();
+    context.queue.write_texture(
+        texture.as_image_copy(),
+        &data,
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(16 * 4),
+            rows_per_image: None,
+        },
+        texture.size(),
+    );
+
+    let new_encoder = || {
+        context
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default())
+    };
+    let mut encoder = new_encoder();
+    fb.generate_mips(&mut encoder);
+    let image = read_mip(&fb, encoder, 1);
+    for (i, pixel) in image.data.chunks(4).enumerate() {
+        let (x, y) = (i as u32 % 8, i as u32 / 8);
+        let value = if (x + y) % 2 == 1 { 255 } else { 0 };
+        assert_eq!(pixel, [value, value, value, 255], "Mip 1 pixel {x}, {y}");
+    }
+    for level in 2..5 {
+        let image = read_mip(&fb, new_encoder(), level);
+        for pixel in image.data.chunks(4) {
+            assert!(
+                pixel
+                    .iter()
+                    .zip([128, 128, 128, 255])
+                    .all(|(&a, e)| a.abs_diff(e) <= 1),
+                "Mip {level}: {pixel:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn history_is_swapped() {
+    let context = context();
+    let mut fb = Framebuffer::new();
+    fb.add_history_attachment(
+        wgpu::TextureFormat::Rgba8Unorm,
+        wgpu::TextureUsages::empty(),
+    );
+    fb.set_resolution(4, 4);
+    fb.try_configure(&context.device).unwrap();
+    let new_encoder = || {
+        context
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default())
+    };
+
+    let mut encoder = new_encoder();
+    fb.set_clear_color(&[1.0, 0.0, 0.0, 1.0]);
+    drop(fb.try_begin_render_pass(&mut encoder).unwrap());
+    context.queue.submit(Some(encoder.finish()));
+    let current = fb.current_view(0).unwrap().clone();
+    let previous = fb.previous_view(0).unwrap().clone();
+
+    fb.swap_history();
+    assert_eq!(fb.current_view(0), Some(&previous));
+    assert_eq!(fb.previous_view(0), Some(&current));
+    // The passes render to the swapped texture
+    let mut encoder = new_encoder();
+    fb.set_clear_color(&[0.0, 1.0, 0.0, 1.0]);
+    drop(fb.try_begin_render_pass(&mut encoder).unwrap());
+
+    let image = read_view(fb.previous_view(0).unwrap(), 4, encoder);
+    assert!(image.data.chunks(4).all(|pixel| pixel == [255, 0, 0, 255]));
+    let image = read_view(fb.current_view(0).unwrap(), 4, new_encoder());
+    assert!(image.data.chunks(4).all(|pixel| pixel == [0, 255, 0, 255]));
+}
+
+#[test]
+fn sample_count_fallback() {
+    let context = context();
+    let mut fb = texture_framebuffer(wgpu::TextureFormat::Rgba8Unorm);
+    fb.set_depth_stencil_format(Some(wgpu::TextureFormat::Depth32Float));
+    let supported = fb.supported_sample_counts(&context.adapter, &context.device);
+    assert_eq!(supported.first(), Some(&1));
+    for format in [
+        wgpu::TextureFormat::Rgba8Unorm,
+        wgpu::TextureFormat::Depth32Float,
+    ] {
+        let flags = context.adapter.get_texture_format_features(format).flags;
+        for &count in &supported[1..] {
+            assert!(flags.sample_count_supported(count), "{format:?} {count}");
+        }
+    }
+
+    fb.set_sample_count(16);
+    fb.set_sample_count_fallback(true);
+    fb.try_configure(&context.device).unwrap();
+    let expected = supported.iter().copied().rfind(|&count| count <= 16);
+    assert_eq!(Some(fb.sample_count()), expected);
+    let texture = fb
+        .msaa_texture(0)
+        .map_or(1, |texture| texture.sample_count());
+    assert_eq!(texture, fb.sample_count());
+}
+
+#[test]
+fn unsupported_sample_count_is_an_error() {
+    let context = context();
+    let mut fb = texture_framebuffer(wgpu::TextureFormat::Rgba8Unorm);
+    // Not a power of two, so never supported
+    fb.set_sample_count(3);
+    assert!(matches!(
+        fb.try_configure(&context.device),
+        Err(FramebufferError::UnsupportedSampleCount(3))
+    ));
+    assert!(fb.needs_configure());
+
+    fb.set_sample_count_fallback(true);
+    fb.try_configure(&context.device).unwrap();
+    assert!(fb.sample_count() < 3);
+}
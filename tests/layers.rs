@@ -0,0 +1,254 @@
+use wgpu_misc::testing::context;
+use wgpu_misc::{Framebuffer, FramebufferError, Image, wgpu};
+
+const SIZE: u32 = 4;
+
+/// Clear color of each layer
+fn layer_color(layer: u32) -> [f64; 4] {
+    [layer as f64 / 8.0, 1.0 - layer as f64 / 8.0, 0.5, 1.0]
+}
+
+fn layered_framebuffer(layer_count: u32, sample_count: u32) -> Framebuffer {
+    let context = context();
+    let mut fb = Framebuffer::new();
+    fb.set_adapter(&context.adapter);
+    fb.add_texture_attachment_with_usage(
+        wgpu::TextureFormat::Rgba8Unorm,
+        wgpu::TextureUsages::COPY_SRC,
+    );
+    fb.set_resolution(SIZE, SIZE);
+    fb.set_layer_count(layer_count);
+    fb.set_sample_count(sample_count);
+    fb.set_depth_stencil_format(Some(wgpu::TextureFormat::Depth32Float));
+    fb
+}
+
+/// Renders a pass to each layer, clearing it to its color
+fn clear_layers(fb: &mut Framebuffer) {
+    let context = context();
+    let mut encoder = context
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    for layer in 0..fb.layer_count() {
+        fb.set_attachment_clear_color(0, &layer_color(layer))
+            .unwrap();
+        drop(fb.begin_render_pass_layer(&mut encoder, layer));
+    }
+    context.queue.submit(Some(encoder.finish()));
+}
+
+fn read_layer(fb: &Framebuffer, layer: u32) -> Image {
+    let context = context();
+    wgpu_misc::block_on(fb.read_attachment_layer(0, layer, &context.device, &context.queue))
+        .unwrap()
+}
+
+fn assert_layers(fb: &Framebuffer) {
+    for layer in 0..fb.layer_count() {
+        let expected = layer_color(layer).map(|c| (c * 255.0).round() as u8);
+        let image = read_layer(fb, layer);
+        for pixel in image.data.chunks(4) {
+            assert!(
+                pixel.iter().zip(expected).all(|(&a, e)| a.abs_diff(e) <= 1),
+                "Layer {layer}: {pixel:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn layers_are_rendered_separately() {
+    let mut fb = layered_framebuffer(3, 1);
+    fb.try_configure(&context().device).unwrap();
+    assert_eq!(fb.attachment_texture(0).unwrap().depth_or_array_layers(), 3);
+    assert_eq!(
+        fb.depth_stencil_texture().unwrap().depth_or_array_layers(),
+        3
+    );
+    assert!(fb.attachment_layer_view(0, 2).is_some());
+    assert!(fb.attachment_layer_view(0, 3).is_none());
+    assert!(matches!(
+        wgpu_misc::block_on(fb.read_attachment_layer(0, 3, &context().device, &context().queue)),
+        Err(FramebufferError::LayerOutOfRange(3))
+    ));
+    clear_layers(&mut fb);
+    assert_layers(&fb);
+}
+
+#[test]
+fn multisampled_layers_share_depth() {
+    let context = context();
+    let mut fb = layered_framebuffer(3, 4);
+    fb.try_configure(&context.device).unwrap();
+    // The single multisample textures are used for the passes to every layer
+    assert_eq!(fb.msaa_texture(0).unwrap().depth_or_array_layers(), 1);
+    assert_eq!(
+        fb.depth_stencil_texture().unwrap().depth_or_array_layers(),
+        1
+    );
+    assert_eq!(fb.depth_stencil_texture().unwrap().sample_count(), 4);
+    clear_layers(&mut fb);
+    assert_layers(&fb);
+}
+
+/// Samples the center of each face of the cube view, to one pixel each
+const CUBE_SHADER: &str = "
+@group(0) @binding(0) var cube: texture_cube<f32>;
+@group(0) @binding(1) var cube_sampler: sampler;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    var directions = array<vec3<f32>, 6>(
+        vec3<f32>(1.0, 0.0, 0.0),
+        vec3<f32>(-1.0, 0.0, 0.0),
+        vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(0.0, -1.0, 0.0),
+        vec3<f32>(0.0, 0.0, 1.0),
+        vec3<f32>(0.0, 0.0, -1.0),
+    );
+    return textureSampleLevel(cube, cube_sampler, directions[u32(position.x)], 0.0);
+}
+";
+
+/// Reads back the faces through the cube view, as cubemaps can't be copied on all backends
+fn read_faces(fb: &Framebuffer) -> Image {
+    let context = context();
+    let device = &context.device;
+    let mut readback = Framebuffer::new();
+    readback.add_texture_attachment_with_usage(
+        wgpu::TextureFormat::Rgba8Unorm,
+        wgpu::TextureUsages::COPY_SRC,
+    );
+    readback.set_resolution(6, 1);
+    readback.try_configure(device).unwrap();
+
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Cube faces"),
+        source: wgpu::ShaderSource::Wgsl(CUBE_SHADER.into()),
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Cube faces"),
+        layout: None,
+        vertex: wgpu::VertexState {
+            module: &module,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &module,
+            entry_point: Some("fs_main"),
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::TextureFormat::Rgba8Unorm.into())],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    });
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Cube faces"),
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(fb.attachment_view(0).unwrap()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&sampler),
+            },
+        ],
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    {
+        let mut pass = readback.begin_render_pass(&mut encoder);
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+    context.queue.submit(Some(encoder.finish()));
+    wgpu_misc::block_on(readback.read_attachment(0, device, &context.queue)).unwrap()
+}
+
+#[test]
+fn cube_faces() {
+    let mut fb = layered_framebuffer(6, 1);
+    fb.set_cube(true);
+    fb.try_configure(&context().device).unwrap();
+    assert!(fb.is_cube());
+    assert_eq!(fb.attachment_texture(0).unwrap().depth_or_array_layers(), 6);
+    clear_layers(&mut fb);
+    let image = read_faces(&fb);
+    for (face, pixel) in image.data.chunks(4).enumerate() {
+        let expected = layer_color(face as u32).map(|c| (c * 255.0).round() as u8);
+        assert!(
+            pixel.iter().zip(expected).all(|(&a, e)| a.abs_diff(e) <= 1),
+            "Face {face}: {pixel:?}"
+        );
+    }
+}
+
+#[test]
+fn invalid_layers() {
+    let context = context();
+    let max_layers = context.device.limits().max_texture_array_layers;
+    for (layer_count, cube, width) in [
+        (0, false, SIZE),
+        (max_layers + 1, false, SIZE),
+        (7, true, SIZE),
+        (6, true, SIZE * 2),
+    ] {
+        let mut fb = layered_framebuffer(layer_count, 1);
+        fb.set_cube(cube);
+        fb.set_resolution(width, SIZE);
+        assert!(
+            matches!(
+                fb.try_configure(&context.device),
+                Err(FramebufferError::InvalidLayers)
+            ),
+            "{layer_count} layers, cube {cube}, width {width}"
+        );
+    }
+}
+
+#[test]
+fn depth_pass_layers() {
+    let context = context();
+    let mut encoder = context
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    let mut fb = layered_framebuffer(2, 1);
+    assert!(matches!(
+        fb.try_begin_depth_pass_layer(&mut encoder, 0),
+        Err(FramebufferError::NotConfigured)
+    ));
+    fb.try_configure(&context.device).unwrap();
+    for layer in 0..2 {
+        drop(fb.try_begin_depth_pass_layer(&mut encoder, layer).unwrap());
+    }
+    assert!(matches!(
+        fb.try_begin_depth_pass_layer(&mut encoder, 2),
+        Err(FramebufferError::LayerOutOfRange(2))
+    ));
+    context.queue.submit(Some(encoder.finish()));
+
+    let mut fb = Framebuffer::new_with_texture(wgpu::TextureFormat::Rgba8Unorm);
+    fb.set_resolution(SIZE, SIZE);
+    fb.try_configure(&context.device).unwrap();
+    let mut encoder = context
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    assert!(matches!(
+        fb.try_begin_depth_pass(&mut encoder),
+        Err(FramebufferError::NoTexture)
+    ));
+}
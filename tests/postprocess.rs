@@ -0,0 +1,143 @@
+use wgpu_misc::testing::context;
+use wgpu_misc::{Framebuffer, FramebufferError, PostProcess, wgpu};
+
+const INVERT: &str = "
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    return vec4<f32>(1.0 - color.rgb, color.a);
+}";
+
+const HALVE: &str = "
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(source, source_sampler, in.uv);
+    return vec4<f32>(color.rgb * 0.5, color.a);
+}";
+
+/// Configured framebuffer with a texture attachment per format, that can be read back
+fn framebuffer(formats: &[wgpu::TextureFormat]) -> Framebuffer {
+    let context = context();
+    let mut fb = Framebuffer::new();
+    fb.set_adapter(&context.adapter);
+    for &format in formats {
+        fb.add_texture_attachment_with_usage(format, wgpu::TextureUsages::COPY_SRC);
+    }
+    fb.set_resolution(8, 8);
+    fb
+}
+
+#[test]
+fn stages_run_in_order() {
+    let context = context();
+    let mut source = framebuffer(&[wgpu::TextureFormat::Rgba8Unorm]);
+    source.set_clear_color(&[0.25, 0.5, 0.75, 1.0]);
+    source.try_configure(&context.device).unwrap();
+    let mut output = framebuffer(&[wgpu::TextureFormat::Rgba8Unorm]);
+    output.set_depth_stencil_format(Some(wgpu::TextureFormat::Depth32Float));
+    output.try_configure(&context.device).unwrap();
+
+    let mut post = PostProcess::new(&context.device);
+    post.add_stage("invert", INVERT, wgpu::TextureFormat::Rgba16Float);
+    post.add_stage("halve", HALVE, wgpu::TextureFormat::Rgba16Float);
+    let mut encoder = context
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    // Clears the source
+    drop(source.try_begin_render_pass(&mut encoder).unwrap());
+    post.run(&mut encoder, &source, 0, &mut output).unwrap();
+    context.queue.submit(Some(encoder.finish()));
+
+    let image =
+        wgpu_misc::block_on(output.read_attachment(0, &context.device, &context.queue)).unwrap();
+    let expected = [0.375, 0.25, 0.125, 1.0].map(|c: f32| (c * 255.0).round() as i32);
+    for pixel in image.data.chunks(4) {
+        for (&actual, expected) in pixel.iter().zip(expected) {
+            assert!((actual as i32 - expected).abs() <= 1, "{pixel:?}");
+        }
+    }
+}
+
+#[test]
+fn output_needs_one_attachment() {
+    let context = context();
+    let mut source = framebuffer(&[wgpu::TextureFormat::Rgba8Unorm]);
+    source.try_configure(&context.device).unwrap();
+    let mut post = PostProcess::new(&context.device);
+    post.add_stage("invert", INVERT, wgpu::TextureFormat::Rgba8Unorm);
+
+    for count in [0, 2] {
+        let mut output = framebuffer(&vec![wgpu::TextureFormat::Rgba8Unorm; count]);
+        let mut encoder = context
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        match post.run(&mut encoder, &source, 0, &mut output) {
+            Err(FramebufferError::AttachmentCount(c)) => assert_eq!(c, count),
+            other => panic!("Unexpected result {other:?}"),
+        }
+    }
+}
+
+#[test]
+fn intermediates_follow_source_size() {
+    let context = context();
+    let mut source = Framebuffer::new();
+    source.add_texture_attachment_with_usage(
+        wgpu::TextureFormat::Rgba8Unorm,
+        wgpu::TextureUsages::COPY_DST,
+    );
+    source.set_resolution(8, 8);
+    source.try_configure(&context.device).unwrap();
+    let mut output = framebuffer(&[wgpu::TextureFormat::Rgba8Unorm]);
+    output.try_configure(&context.device).unwrap();
+    let mut post = PostProcess::new(&context.device);
+    post.add_stage("invert", INVERT, wgpu::TextureFormat::Rgba16Float);
+    post.add_stage("halve", HALVE, wgpu::TextureFormat::Rgba16Float);
+    let new_encoder = || {
+        context
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default())
+    };
+    let mut encoder = new_encoder();
+    post.run(&mut encoder, &source, 0, &mut output).unwrap();
+    context.queue.submit(Some(encoder.finish()));
+
+    // A checkerboard of single pixels, that would be blurred by an
+    // intermediate of the previous size
+    source.set_resolution(16, 16);
+    source.try_configure(&context.device).unwrap();
+    output.set_resolution(16, 16);
+    output.try_configure(&context.device).unwrap();
+    let white = |i: usize| (i % 16 + i / 16) % 2 == 1;
+    let data: Vec<u8> = (0..16 * 16)
+        .flat_map(|i| {
+            let value = if white(i) { 255 } else { 0 };
+            [value, value, value, 255]
+        })
+        .collect();
+    let texture = source.attachment_texture(0).unwrap();
+    context.queue.write_texture(
+        texture.as_image_copy(),
+        &data,
+        wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(16 * 4),
+            rows_per_image: None,
+        },
+        texture.size(),
+    );
+    let mut encoder = new_encoder();
+    post.run(&mut encoder, &source, 0, &mut output).unwrap();
+    context.queue.submit(Some(encoder.finish()));
+
+    let image =
+        wgpu_misc::block_on(output.read_attachment(0, &context.device, &context.queue)).unwrap();
+    assert_eq!((image.width, image.height), (16, 16));
+    for (i, pixel) in image.data.chunks(4).enumerate() {
+        let value = if white(i) { 0 } else { 128 };
+        assert!(
+            pixel[..3].iter().all(|&c| c.abs_diff(value) <= 1),
+            "Pixel {i}: {pixel:?}"
+        );
+    }
+}
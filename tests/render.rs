@@ -0,0 +1,229 @@
+use wgpu_misc::testing::{RenderMismatch, RenderTest, UPDATE_REFERENCES_ENV, context};
+use wgpu_misc::{Framebuffer, FramebufferError, assert_render_matches, wgpu};
+
+const SHADER: &str = r#"
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+// Instance 0 is a red triangle in front, instance 1 a green one behind it,
+// shifted to the right
+@vertex
+fn vs_main(
+    @builtin(vertex_index) index: u32,
+    @builtin(instance_index) instance: u32,
+) -> VertexOutput {
+    var positions = array<vec2<f32>, 3>(
+        vec2<f32>(-0.8, -0.8),
+        vec2<f32>(0.8, -0.8),
+        vec2<f32>(0.0, 0.8),
+    );
+    let offset = f32(instance) * 0.3;
+    var out: VertexOutput;
+    out.position = vec4<f32>(positions[index] + vec2<f32>(offset, 0.0), 0.25 + offset, 1.0);
+    out.color = select(vec4<f32>(0.0, 1.0, 0.0, 1.0), vec4<f32>(1.0, 0.0, 0.0, 1.0), instance == 0u);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
+"#;
+
+fn triangle_pipeline(
+    sample_count: u32,
+    depth_stencil_format: Option<wgpu::TextureFormat>,
+) -> wgpu::RenderPipeline {
+    let device = &context().device;
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Triangle shader"),
+        source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Triangle pipeline"),
+        layout: None,
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: Some("fs_main"),
+            compilation_options: Default::default(),
+            targets: &[Some(wgpu::TextureFormat::Rgba8UnormSrgb.into())],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: depth_stencil_format.map(|format| wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+        cache: None,
+    })
+}
+
+#[test]
+fn clear_color() {
+    assert_render_matches!(
+        RenderTest::new("clear_color").clear_color([0.2, 0.4, 0.6, 1.0]),
+        |_pass| {}
+    );
+}
+
+#[test]
+fn triangle() {
+    let pipeline = triangle_pipeline(1, None);
+    assert_render_matches!("triangle", |pass| {
+        pass.set_pipeline(&pipeline);
+        pass.draw(0..3, 0..1);
+    });
+}
+
+#[test]
+fn msaa_triangle() {
+    let pipeline = triangle_pipeline(4, None);
+    assert_render_matches!(RenderTest::new("msaa_triangle").sample_count(4), |pass| {
+        pass.set_pipeline(&pipeline);
+        pass.draw(0..3, 0..1);
+    });
+}
+
+#[test]
+fn depth_test() {
+    let format = wgpu::TextureFormat::Depth32Float;
+    let pipeline = triangle_pipeline(1, Some(format));
+    // The triangle behind is drawn last, and only shows where it isn't covered
+    assert_render_matches!(
+        RenderTest::new("depth_test").depth_stencil_format(Some(format)),
+        |pass| {
+            pass.set_pipeline(&pipeline);
+            pass.draw(0..3, 0..2);
+        }
+    );
+}
+
+#[test]
+fn msaa_depth_test() {
+    let format = wgpu::TextureFormat::Depth24PlusStencil8;
+    let pipeline = triangle_pipeline(4, Some(format));
+    assert_render_matches!(
+        RenderTest::new("msaa_depth_test")
+            .sample_count(4)
+            .depth_stencil_format(Some(format)),
+        |pass| {
+            pass.set_pipeline(&pipeline);
+            pass.draw(0..3, 0..2);
+        }
+    );
+}
+
+#[test]
+fn occlusion_query() {
+    let context = context();
+    let format = wgpu::TextureFormat::Depth32Float;
+    let pipeline = triangle_pipeline(1, Some(format));
+    let mut fb = Framebuffer::new_with_texture(wgpu::TextureFormat::Rgba8UnormSrgb);
+    fb.set_resolution(64, 64);
+    fb.set_depth_stencil_format(Some(format));
+    fb.set_occlusion_query_count(2);
+    let read = |fb: &Framebuffer, queries| {
+        wgpu_misc::block_on(fb.read_occlusion_queries(queries, &context.device, &context.queue))
+    };
+    assert!(matches!(
+        read(&fb, 0..1),
+        Err(FramebufferError::NotConfigured)
+    ));
+    fb.try_configure(&context.device).unwrap();
+    assert!(matches!(
+        read(&fb, 0..3),
+        Err(FramebufferError::QueryOutOfRange(2))
+    ));
+
+    let mut encoder = context
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    {
+        let mut pass = fb.try_begin_render_pass(&mut encoder).unwrap();
+        pass.set_pipeline(&pipeline);
+        pass.begin_occlusion_query(0);
+        pass.draw(0..3, 0..1);
+        pass.end_occlusion_query();
+        // Nothing drawn
+        pass.begin_occlusion_query(1);
+        pass.end_occlusion_query();
+    }
+    context.queue.submit(Some(encoder.finish()));
+    let counts = read(&fb, 0..2).unwrap();
+    assert!(counts[0] > 0, "{counts:?}");
+    assert_eq!(counts[1], 0);
+}
+
+#[test]
+fn msaa_passes_resolve_last() {
+    let context = context();
+    let pipeline = triangle_pipeline(4, None);
+    let mut fb = Framebuffer::new();
+    fb.add_texture_attachment_with_usage(
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+        wgpu::TextureUsages::COPY_SRC,
+    );
+    fb.set_resolution(64, 64);
+    fb.set_sample_count(4);
+    fb.try_configure(&context.device).unwrap();
+    fb.set_attachment_store(0, true).unwrap();
+
+    // The passes are prepared once for each resolve setting
+    for _frame in 0..2 {
+        let mut encoder = context
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        fb.set_msaa_resolve(false);
+        fb.set_attachment_clear_color(0, &[0.0, 0.0, 1.0, 1.0])
+            .unwrap();
+        drop(fb.try_begin_render_pass(&mut encoder).unwrap());
+        fb.set_msaa_resolve(true);
+        fb.set_attachment_load_op(0, wgpu::LoadOp::Load).unwrap();
+        {
+            let mut pass = fb.try_begin_render_pass(&mut encoder).unwrap();
+            pass.set_pipeline(&pipeline);
+            pass.draw(0..3, 0..1);
+        }
+        context.queue.submit(Some(encoder.finish()));
+
+        let image =
+            wgpu_misc::block_on(fb.read_attachment(0, &context.device, &context.queue)).unwrap();
+        let pixel = |x: usize, y: usize| &image.data[(y * 64 + x) * 4..][..4];
+        // The corner is only cleared by the first pass, the center is the triangle
+        assert_eq!(pixel(0, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(32, 32), [255, 0, 0, 255]);
+    }
+}
+
+#[test]
+fn mismatch_is_reported() {
+    // It would overwrite the reference
+    if std::env::var_os(UPDATE_REFERENCES_ENV).is_some() {
+        return;
+    }
+    // A different clear color than the reference
+    let test = RenderTest::new("clear_color")
+        .clear_color([0.6, 0.4, 0.2, 1.0])
+        .reference_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/reference"));
+    match test.check(|_pass| {}) {
+        Err(RenderMismatch::Pixels { count, .. }) => {
+            assert_eq!(count, 64 * 64)
+        }
+        result => panic!("Expected a pixel mismatch, got {result:?}"),
+    }
+}
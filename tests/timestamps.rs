@@ -0,0 +1,71 @@
+use wgpu_misc::testing::context;
+use wgpu_misc::{Framebuffer, wgpu};
+
+/// Framebuffer with timestamps for 4 passes, configured for the device
+fn timed_framebuffer(device: &wgpu::Device) -> Framebuffer {
+    let mut fb = Framebuffer::new_with_texture(wgpu::TextureFormat::Rgba8Unorm);
+    fb.set_adapter(&context().adapter);
+    fb.set_resolution(8, 8);
+    fb.set_timestamp_pass_count(4);
+    fb.try_configure(device).unwrap();
+    fb
+}
+
+/// Begins `passes` passes, resolves and submits them, and reads the timestamps
+fn run_frame(fb: &mut Framebuffer, device: &wgpu::Device, queue: &wgpu::Queue, passes: u32) {
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    for _ in 0..passes {
+        drop(fb.try_begin_render_pass(&mut encoder).unwrap());
+    }
+    fb.resolve_timestamps(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+    fb.read_timestamps(queue);
+}
+
+#[test]
+fn durations_per_pass() {
+    let adapter = &context().adapter;
+    if !adapter.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+        eprintln!("Skipping, the adapter doesn't support timestamp queries");
+        return;
+    }
+    let (device, queue) = wgpu_misc::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+        required_features: wgpu::Features::TIMESTAMP_QUERY,
+        ..Default::default()
+    }))
+    .unwrap();
+    let mut fb = timed_framebuffer(&device);
+
+    run_frame(&mut fb, &device, &queue, 2);
+    // Mapping has started, but the GPU was not waited for
+    assert!(fb.pass_durations_ms().is_empty());
+    device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
+    fb.read_timestamps(&queue);
+    assert_eq!(fb.pass_durations_ms().len(), 2);
+
+    // Passes over the count get no timestamps
+    run_frame(&mut fb, &device, &queue, 5);
+    device.poll(wgpu::PollType::wait_indefinitely()).unwrap();
+    fb.read_timestamps(&queue);
+    assert_eq!(fb.pass_durations_ms().len(), 4);
+}
+
+#[test]
+fn no_durations_without_feature() {
+    let context = context();
+    // The shared test device doesn't enable timestamp queries
+    assert!(
+        !context
+            .device
+            .features()
+            .contains(wgpu::Features::TIMESTAMP_QUERY)
+    );
+    let mut fb = timed_framebuffer(&context.device);
+    run_frame(&mut fb, &context.device, &context.queue, 2);
+    context
+        .device
+        .poll(wgpu::PollType::wait_indefinitely())
+        .unwrap();
+    fb.read_timestamps(&context.queue);
+    assert!(fb.pass_durations_ms().is_empty());
+}
@@ -0,0 +1,62 @@
+use wgpu_misc::testing::context;
+use wgpu_misc::{Framebuffer, WindowFramebuffers, wgpu};
+
+/// Framebuffers of two windows, configured at 16x16
+fn windows() -> WindowFramebuffers<u32> {
+    let mut windows = WindowFramebuffers::new();
+    for id in 0..2 {
+        let mut fb = Framebuffer::new_with_texture(wgpu::TextureFormat::Rgba8Unorm);
+        fb.set_resolution(16, 16);
+        windows.insert(id, fb);
+    }
+    windows.configure(&context().device);
+    windows
+}
+
+#[test]
+fn resize_dirties_one_window() {
+    let mut windows = windows();
+    windows.resize(&0, 32, 8);
+    assert!(windows.get(&0).unwrap().needs_configure());
+    assert!(!windows.get(&1).unwrap().needs_configure());
+    // Unknown windows are ignored
+    windows.resize(&2, 32, 8);
+    assert_eq!(windows.len(), 2);
+
+    windows.configure(&context().device);
+    let fb = windows.get(&0).unwrap();
+    assert!(!fb.needs_configure());
+    assert_eq!((fb.width(), fb.height()), (32, 8));
+    let texture = fb.attachment_texture(0).unwrap();
+    assert_eq!((texture.width(), texture.height()), (32, 8));
+}
+
+#[test]
+fn zero_size_windows_are_skipped() {
+    let mut windows = windows();
+    // Minimized
+    windows.resize(&0, 0, 0);
+    windows.resize(&1, 24, 24);
+    windows.try_configure(&context().device).unwrap();
+    assert!(windows.get(&0).unwrap().needs_configure());
+    let fb = windows.get(&1).unwrap();
+    assert!(!fb.needs_configure());
+    assert_eq!(fb.attachment_texture(0).unwrap().width(), 24);
+}
+
+#[test]
+fn present_all_without_frames() {
+    let context = context();
+    let mut windows = windows();
+    let mut encoder = context
+        .device
+        .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+    for (_id, fb) in windows.iter_mut() {
+        drop(fb.try_begin_render_pass(&mut encoder).unwrap());
+    }
+    context.queue.submit(Some(encoder.finish()));
+    // Texture attachments have no frames to present
+    assert!(windows.iter().all(|(_id, fb)| !fb.needs_present()));
+    windows.present_all();
+    assert!(windows.iter().all(|(_id, fb)| !fb.needs_configure()));
+}